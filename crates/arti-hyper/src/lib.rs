@@ -32,6 +32,7 @@
 
 use std::future::Future;
 use std::io::Error;
+use std::net::SocketAddr;
 use std::pin::Pin;
 use std::sync::Arc;
 use std::task::{Context, Poll};
@@ -42,9 +43,11 @@ use hyper::http::uri::Scheme;
 use hyper::http::Uri;
 use hyper::service::Service;
 use pin_project::pin_project;
+use sha2::{Digest, Sha256};
 use thiserror::Error;
 use tls_api::TlsConnector as TlsConn; // This is different from tor_rtompat::TlsConnector
-use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tls_api::TlsConnectorBuilder as TlsConnBuilder;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf};
 use tor_rtcompat::Runtime;
 
 /// Error making or using http connection
@@ -74,6 +77,31 @@ pub enum ConnectionError {
     /// TLS connection failed
     #[error("TLS connection failed")]
     TLS(#[source] Arc<anyhow::Error>),
+
+    /// The origin's certificate did not match the configured pin.
+    #[error("certificate pin mismatch for {host:?}")]
+    CertPinMismatch {
+        /// The host we were connecting to.
+        host: String,
+    },
+
+    /// Failed to write the PROXY protocol header to the stream.
+    #[error("failed to write PROXY protocol header")]
+    ProxyProtocol(#[source] Arc<std::io::Error>),
+
+    /// The caller-supplied stream provider failed to produce a stream.
+    #[error("stream provider failed")]
+    StreamProvider(#[source] Arc<std::io::Error>),
+
+    /// A certificate pin was configured, but no certificate extractor was
+    /// registered to enforce it.
+    ///
+    /// This happens if `tls_config.pinned_cert_sha256` was set directly
+    /// (e.g. via [`ArtiHttpConnectorBuilder::tls_config`]) instead of through
+    /// [`ArtiHttpConnectorBuilder::pinned_cert_sha256`], which is the only
+    /// way to register the extractor this enforcement needs.
+    #[error("certificate pin configured without a certificate extractor")]
+    PinningUnconfigured,
 }
 
 /// We implement this for form's sake
@@ -87,7 +115,179 @@ impl tor_error::HasKind for ConnectionError {
             CE::MissingHostname{..}      => EK::BadApiUsage,
             CE::Arti(e)                  => e.kind(),
             CE::TLS(_)                   => EK::RemoteProtocolFailed,
+            CE::CertPinMismatch{..}      => EK::RemoteProtocolFailed,
+            CE::ProxyProtocol(_)         => EK::RemoteProtocolFailed,
+            CE::StreamProvider(_)        => EK::RemoteProtocolFailed,
+            CE::PinningUnconfigured      => EK::BadApiUsage,
+        }
+    }
+}
+
+/// Configuration for the TLS session used *across* Tor to reach the origin.
+///
+/// Custom trust anchors, client certificates, and "accept invalid certs"
+/// switches are backend-specific `tls_api` builder options; set those
+/// directly on the `TC::Builder` passed to [`ArtiHttpConnector::builder`]
+/// instead. `TlsConfig` only covers verification this crate can do itself,
+/// independent of the backend: pinning a certificate by its SHA-256
+/// fingerprint, which is useful when talking to an onion service whose
+/// certificate is not in the public web PKI.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct TlsConfig {
+    /// If set, require the origin's leaf certificate to have exactly this
+    /// SHA-256 fingerprint, regardless of whether it otherwise validates.
+    pub pinned_cert_sha256: Option<[u8; 32]>,
+}
+
+impl TlsConfig {
+    /// Return an error unless `cert_der` satisfies the configured pin.
+    ///
+    /// When no pin is configured this always succeeds. `host` is used only to
+    /// build a descriptive error.
+    fn check_pin(&self, host: &str, cert_der: &[u8]) -> Result<(), ConnectionError> {
+        let pin = match &self.pinned_cert_sha256 {
+            Some(pin) => pin,
+            None => return Ok(()),
+        };
+        let got: [u8; 32] = Sha256::digest(cert_der).into();
+        if &got == pin {
+            Ok(())
+        } else {
+            Err(ConnectionError::CertPinMismatch {
+                host: host.to_owned(),
+            })
+        }
+    }
+}
+
+/// Exposes the origin's leaf certificate in DER form after a TLS handshake.
+///
+/// `tls_api`'s `TlsStream` does not expose the peer certificate in a
+/// backend-agnostic way (the concrete certificate type differs between its
+/// native-tls, rustls, and openssl backends), so enforcing
+/// [`TlsConfig::pinned_cert_sha256`] needs this crate's own extension point:
+/// implement it for your chosen `TC::TlsStream` to opt in to pinning.
+///
+/// This is only required of callers who use
+/// [`ArtiHttpConnectorBuilder::pinned_cert_sha256`]; it is not a bound on
+/// [`ArtiHttpConnector`] itself, so callers who never pin a certificate don't
+/// need an impl.
+pub trait PeerCertDer {
+    /// Return the origin's leaf certificate in DER form, if the handshake
+    /// completed and a certificate is available.
+    fn peer_certificate_der(&self) -> Option<Vec<u8>>;
+}
+
+/// Extracts the origin's leaf certificate (DER-encoded) from a completed TLS
+/// handshake, for enforcing [`TlsConfig::pinned_cert_sha256`].
+///
+/// Built from a [`PeerCertDer`] impl by
+/// [`ArtiHttpConnectorBuilder::pinned_cert_sha256`] and stashed on the
+/// connector, so that the generic `Service` impl doesn't need a
+/// `TC::TlsStream: PeerCertDer` bound of its own.
+type CertExtractor<TC> = Arc<dyn Fn(&<TC as TlsConn>::TlsStream) -> Option<Vec<u8>> + Send + Sync>;
+
+/// Which version of the PROXY protocol header to emit to the origin.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum ProxyProtocolVersion {
+    /// The human-readable v1 header.
+    V1,
+    /// The binary v2 header.
+    V2,
+}
+
+/// Configuration for emitting a PROXY protocol header to the origin after the
+/// Tor stream is established.
+///
+/// Since Tor hides the real client address, the advertised source and
+/// destination are supplied by the caller. When either is `None` we fall back
+/// to `UNKNOWN` (v1) or the `LOCAL` command (v2).
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct ProxyProtocolConfig {
+    /// Which header version to send.
+    pub version: ProxyProtocolVersion,
+    /// The source address to advertise, or `None` for `UNKNOWN`.
+    pub source: Option<SocketAddr>,
+    /// The destination address to advertise, or `None` for `UNKNOWN`.
+    pub dest: Option<SocketAddr>,
+}
+
+impl ProxyProtocolConfig {
+    /// Encode this header as bytes to prepend to the stream.
+    fn encode(&self) -> Vec<u8> {
+        match self.version {
+            ProxyProtocolVersion::V1 => self.encode_v1(),
+            ProxyProtocolVersion::V2 => self.encode_v2(),
+        }
+    }
+
+    /// Encode a v1 (ASCII) header.
+    fn encode_v1(&self) -> Vec<u8> {
+        match (self.source, self.dest) {
+            (Some(SocketAddr::V4(s)), Some(SocketAddr::V4(d))) => format!(
+                "PROXY TCP4 {} {} {} {}\r\n",
+                s.ip(),
+                d.ip(),
+                s.port(),
+                d.port()
+            )
+            .into_bytes(),
+            (Some(SocketAddr::V6(s)), Some(SocketAddr::V6(d))) => format!(
+                "PROXY TCP6 {} {} {} {}\r\n",
+                s.ip(),
+                d.ip(),
+                s.port(),
+                d.port()
+            )
+            .into_bytes(),
+            // Mixed families or a missing address: we can't describe the
+            // connection, so announce it as unknown.
+            _ => b"PROXY UNKNOWN\r\n".to_vec(),
+        }
+    }
+
+    /// Encode a v2 (binary) header.
+    fn encode_v2(&self) -> Vec<u8> {
+        /// The 12-byte v2 signature.
+        const SIG: [u8; 12] = [
+            0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A,
+        ];
+        let mut out = SIG.to_vec();
+        match (self.source, self.dest) {
+            (Some(SocketAddr::V4(s)), Some(SocketAddr::V4(d))) => {
+                out.push(0x21); // version 2, PROXY command
+                out.push(0x11); // AF_INET + STREAM
+                let mut addrs = Vec::with_capacity(12);
+                addrs.extend_from_slice(&s.ip().octets());
+                addrs.extend_from_slice(&d.ip().octets());
+                addrs.extend_from_slice(&s.port().to_be_bytes());
+                addrs.extend_from_slice(&d.port().to_be_bytes());
+                out.extend_from_slice(&(addrs.len() as u16).to_be_bytes());
+                out.extend_from_slice(&addrs);
+            }
+            (Some(SocketAddr::V6(s)), Some(SocketAddr::V6(d))) => {
+                out.push(0x21); // version 2, PROXY command
+                out.push(0x21); // AF_INET6 + STREAM
+                let mut addrs = Vec::with_capacity(36);
+                addrs.extend_from_slice(&s.ip().octets());
+                addrs.extend_from_slice(&d.ip().octets());
+                addrs.extend_from_slice(&s.port().to_be_bytes());
+                addrs.extend_from_slice(&d.port().to_be_bytes());
+                out.extend_from_slice(&(addrs.len() as u16).to_be_bytes());
+                out.extend_from_slice(&addrs);
+            }
+            // Mixed families or a missing address: emit a LOCAL command with
+            // the unspecified address family and no address block.
+            _ => {
+                out.push(0x20); // version 2, LOCAL command
+                out.push(0x00); // AF_UNSPEC
+                out.extend_from_slice(&0u16.to_be_bytes());
+            }
         }
+        out
     }
 }
 
@@ -105,6 +305,37 @@ pub struct ArtiHttpConnector<R: Runtime, TC: TlsConn> {
 
     /// TLS for using across Tor.
     tls_conn: Arc<TC>,
+
+    /// ALPN protocols advertised when setting up TLS across Tor.
+    ///
+    /// These were already set on `tls_conn`'s builder via
+    /// `tls_api::TlsConnectorBuilder::set_alpn_protocols` before it was
+    /// built; we keep the list here so that we know whether a negotiated
+    /// `h2` is expected, and so that hyper can be told to drive an HTTP/2
+    /// connection.
+    alpn: Arc<Vec<Vec<u8>>>,
+
+    /// Configuration for the Tor-to-origin TLS session.
+    ///
+    /// The certificate pin (if any) is enforced here after the handshake
+    /// completes, using `cert_extractor` to read the certificate.
+    tls_config: Arc<TlsConfig>,
+
+    /// If `tls_config.pinned_cert_sha256` is set, the extractor that reads
+    /// the peer certificate off `TC::TlsStream` to check it against the pin.
+    ///
+    /// Registered by [`ArtiHttpConnectorBuilder::pinned_cert_sha256`]; absent
+    /// otherwise, including when a caller sets the pin directly via
+    /// [`ArtiHttpConnectorBuilder::tls_config`] instead.
+    cert_extractor: Option<CertExtractor<TC>>,
+
+    /// If set, a PROXY protocol header to prepend to the stream once the Tor
+    /// connection is established and before any TLS/HTTP traffic.
+    proxy_protocol: Arc<Option<ProxyProtocolConfig>>,
+
+    /// If set, a closure that supplies the raw stream to the origin instead of
+    /// the default `client.connect()` transport.
+    stream_provider: Option<StreamProvider>,
 }
 
 // #[derive(Clone)] infers a TC: Clone bound
@@ -112,15 +343,166 @@ impl<R: Runtime, TC: TlsConn> Clone for ArtiHttpConnector<R, TC> {
     fn clone(&self) -> Self {
         let client = self.client.clone();
         let tls_conn = self.tls_conn.clone();
-        Self { client, tls_conn }
+        let alpn = self.alpn.clone();
+        let tls_config = self.tls_config.clone();
+        let cert_extractor = self.cert_extractor.clone();
+        let proxy_protocol = self.proxy_protocol.clone();
+        let stream_provider = self.stream_provider.clone();
+        Self {
+            client,
+            tls_conn,
+            alpn,
+            tls_config,
+            cert_extractor,
+            proxy_protocol,
+            stream_provider,
+        }
     }
 }
 
 impl<R: Runtime, TC: TlsConn> ArtiHttpConnector<R, TC> {
-    /// Make a new `ArtiHttpConnector` using an Arti `TorClient` object.
-    pub fn new(client: TorClient<R>, tls_conn: TC) -> Self {
-        let tls_conn = tls_conn.into();
-        Self { client, tls_conn }
+    /// Make a new `ArtiHttpConnector` using an Arti `TorClient` object and a
+    /// `tls_api` connector builder.
+    ///
+    /// Use `tls_builder` to set up any backend-specific TLS options (custom
+    /// trust anchors, client certificates, `danger_accept_invalid_certs`,
+    /// and so on) before passing it in; this constructor finishes the build.
+    pub fn new(client: TorClient<R>, tls_builder: TC::Builder) -> Result<Self, ConnectionError> {
+        Self::builder(client, tls_builder).build()
+    }
+
+    /// Make a new `ArtiHttpConnector` that advertises the given ALPN
+    /// protocols (e.g. `[b"h2".to_vec(), b"http/1.1".to_vec()]`) across Tor.
+    ///
+    /// When `h2` is negotiated with the origin, the returned connections
+    /// report themselves to hyper as HTTP/2, so requests to a single origin
+    /// can be multiplexed over one Tor stream.
+    pub fn new_with_alpn(
+        client: TorClient<R>,
+        tls_builder: TC::Builder,
+        alpn: Vec<Vec<u8>>,
+    ) -> Result<Self, ConnectionError> {
+        Self::builder(client, tls_builder).alpn(alpn).build()
+    }
+
+    /// Begin building an `ArtiHttpConnector` with non-default ALPN or TLS
+    /// configuration.
+    pub fn builder(
+        client: TorClient<R>,
+        tls_builder: TC::Builder,
+    ) -> ArtiHttpConnectorBuilder<R, TC> {
+        ArtiHttpConnectorBuilder {
+            client,
+            tls_builder,
+            alpn: Vec::new(),
+            tls_config: TlsConfig::default(),
+            cert_extractor: None,
+            proxy_protocol: None,
+            stream_provider: None,
+        }
+    }
+}
+
+/// Builder for an [`ArtiHttpConnector`].
+///
+/// Lets a caller set the ALPN protocols to advertise and the [`TlsConfig`] used
+/// for the Tor-to-origin TLS session before constructing the connector.
+pub struct ArtiHttpConnectorBuilder<R: Runtime, TC: TlsConn> {
+    /// The client the connector will use.
+    client: TorClient<R>,
+    /// The `tls_api` connector builder used across Tor; ALPN is layered onto
+    /// this before it is finished with `TlsConnectorBuilder::build`.
+    tls_builder: TC::Builder,
+    /// ALPN protocols to advertise.
+    alpn: Vec<Vec<u8>>,
+    /// TLS verification configuration.
+    tls_config: TlsConfig,
+    /// See [`ArtiHttpConnector::cert_extractor`].
+    cert_extractor: Option<CertExtractor<TC>>,
+    /// Optional PROXY protocol header to emit to the origin.
+    proxy_protocol: Option<ProxyProtocolConfig>,
+    /// Optional pluggable stream provider.
+    stream_provider: Option<StreamProvider>,
+}
+
+impl<R: Runtime, TC: TlsConn> ArtiHttpConnectorBuilder<R, TC> {
+    /// Set the ALPN protocols to advertise across Tor.
+    pub fn alpn(mut self, alpn: Vec<Vec<u8>>) -> Self {
+        self.alpn = alpn;
+        self
+    }
+
+    /// Set the [`TlsConfig`] for the Tor-to-origin TLS session.
+    ///
+    /// If `tls_config.pinned_cert_sha256` is set, use
+    /// [`Self::pinned_cert_sha256`] instead (or in addition) to also
+    /// register the certificate extractor the pin needs; setting the pin
+    /// through this method alone leaves it unenforced and [`build`](Self::build)'s
+    /// connector will fail every pinned request with
+    /// [`ConnectionError::PinningUnconfigured`].
+    pub fn tls_config(mut self, tls_config: TlsConfig) -> Self {
+        self.tls_config = tls_config;
+        self
+    }
+
+    /// Require the origin's leaf certificate to have exactly this SHA-256
+    /// fingerprint, regardless of whether it otherwise validates.
+    ///
+    /// `tls_api`'s `TlsStream` does not expose the peer certificate in a
+    /// backend-agnostic way, so this requires `TC::TlsStream:`
+    /// [`PeerCertDer`] — implement it for your chosen backend's stream type
+    /// to use pinning. That bound is local to this method: callers who don't
+    /// pin a certificate never need to satisfy it.
+    pub fn pinned_cert_sha256(mut self, pin: [u8; 32]) -> Self
+    where
+        TC::TlsStream: PeerCertDer,
+    {
+        self.tls_config.pinned_cert_sha256 = Some(pin);
+        self.cert_extractor = Some(Arc::new(TC::TlsStream::peer_certificate_der));
+        self
+    }
+
+    /// Emit a PROXY protocol header to the origin once connected.
+    pub fn proxy_protocol(mut self, proxy_protocol: ProxyProtocolConfig) -> Self {
+        self.proxy_protocol = Some(proxy_protocol);
+        self
+    }
+
+    /// Use `provider` to obtain the raw stream to each origin, instead of the
+    /// default Tor transport.
+    ///
+    /// The closure is given the host and port parsed from the request URI and
+    /// must return any `AsyncRead + AsyncWrite + Send + Unpin` stream.
+    pub fn stream_provider(mut self, provider: StreamProvider) -> Self {
+        self.stream_provider = Some(provider);
+        self
+    }
+
+    /// Construct the [`ArtiHttpConnector`], advertising `self.alpn` (if any)
+    /// during the TLS handshake.
+    ///
+    /// Fails if the underlying `tls_api` builder rejects the ALPN protocols
+    /// or fails to build the connector.
+    pub fn build(mut self) -> Result<ArtiHttpConnector<R, TC>, ConnectionError> {
+        if !self.alpn.is_empty() {
+            let protocols: Vec<&[u8]> = self.alpn.iter().map(Vec::as_slice).collect();
+            self.tls_builder
+                .set_alpn_protocols(&protocols)
+                .map_err(|e| ConnectionError::TLS(Arc::new(e.into())))?;
+        }
+        let tls_conn = self
+            .tls_builder
+            .build()
+            .map_err(|e| ConnectionError::TLS(Arc::new(e.into())))?;
+        Ok(ArtiHttpConnector {
+            client: self.client,
+            tls_conn: Arc::new(tls_conn),
+            alpn: Arc::new(self.alpn),
+            tls_config: Arc::new(self.tls_config),
+            cert_extractor: self.cert_extractor,
+            proxy_protocol: Arc::new(self.proxy_protocol),
+            stream_provider: self.stream_provider,
+        })
     }
 }
 
@@ -131,13 +513,155 @@ pub struct ArtiHttpConnection<TC: TlsConn> {
     /// The stream
     #[pin]
     inner: MaybeHttpsStream<TC>,
+
+    /// Whether `h2` was negotiated via ALPN during the TLS handshake.
+    alpn_h2: bool,
+}
+
+/// A type-erased bidirectional stream to an origin server.
+///
+/// Implemented for any `AsyncRead + AsyncWrite + Send + Unpin`, this lets a
+/// [`StreamProvider`] hand the connector a pre-opened stream of any kind — an
+/// onion-service rendezvous stream, a unix-domain socket, or an in-memory
+/// duplex for testing — in place of the default `client.connect()` transport.
+pub trait OriginStream: Send + Unpin {
+    /// See [`AsyncRead::poll_read`].
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(), Error>>;
+    /// See [`AsyncWrite::poll_write`].
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, Error>>;
+    /// See [`AsyncWrite::poll_flush`].
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>>;
+    /// See [`AsyncWrite::poll_shutdown`].
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>>;
+}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> OriginStream for T {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(), Error>> {
+        AsyncRead::poll_read(self, cx, buf)
+    }
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, Error>> {
+        AsyncWrite::poll_write(self, cx, buf)
+    }
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        AsyncWrite::poll_flush(self, cx)
+    }
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        AsyncWrite::poll_shutdown(self, cx)
+    }
+}
+
+impl AsyncRead for Box<dyn OriginStream> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(), Error>> {
+        Pin::new(&mut **self).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Box<dyn OriginStream> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, Error>> {
+        Pin::new(&mut **self).poll_write(cx, buf)
+    }
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Pin::new(&mut **self).poll_flush(cx)
+    }
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Pin::new(&mut **self).poll_shutdown(cx)
+    }
+}
+
+/// The raw (pre-TLS) stream to the origin.
+///
+/// Either a Tor [`DataStream`] opened by the default transport, or a
+/// caller-supplied stream from a [`StreamProvider`].
+enum RawStream {
+    /// A Tor stream. (`DataStream` may not be `Unpin`, so we box and pin it.)
+    Tor(Pin<Box<DataStream>>),
+    /// A caller-supplied stream.
+    Custom(Box<dyn OriginStream>),
+}
+
+impl AsyncRead for RawStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(), Error>> {
+        match self.get_mut() {
+            RawStream::Tor(ds) => ds.as_mut().poll_read(cx, buf),
+            RawStream::Custom(s) => Pin::new(s).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for RawStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, Error>> {
+        match self.get_mut() {
+            RawStream::Tor(ds) => ds.as_mut().poll_write(cx, buf),
+            RawStream::Custom(s) => Pin::new(s).poll_write(cx, buf),
+        }
+    }
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        match self.get_mut() {
+            RawStream::Tor(ds) => ds.as_mut().poll_flush(cx),
+            RawStream::Custom(s) => Pin::new(s).poll_flush(cx),
+        }
+    }
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        match self.get_mut() {
+            RawStream::Tor(ds) => ds.as_mut().poll_shutdown(cx),
+            RawStream::Custom(s) => Pin::new(s).poll_shutdown(cx),
+        }
+    }
 }
 
+/// A pluggable source of origin streams.
+///
+/// Given the host and port parsed from the request URI, returns a future
+/// resolving to a stream to use in place of the default Tor transport. This
+/// mirrors the transport abstraction reqwest's connector uses to swap between
+/// its built-in and custom transports.
+pub type StreamProvider = Arc<
+    dyn Fn(
+            String,
+            u16,
+        )
+            -> Pin<Box<dyn Future<Output = std::io::Result<Box<dyn OriginStream>>> + Send>>
+        + Send
+        + Sync,
+>;
+
 /// The actual actual stream; might be TLS, might not
 #[pin_project(project = MaybeHttpsStreamProj)]
 enum MaybeHttpsStream<TC: TlsConn> {
     /// http
-    Http(Pin<Box<DataStream>>), // Tc:TlsStream is generally boxed; box this one too
+    Http(#[pin] RawStream),
 
     /// https
     Https(#[pin] TC::TlsStream),
@@ -145,7 +669,12 @@ enum MaybeHttpsStream<TC: TlsConn> {
 
 impl<TC: TlsConn> Connection for ArtiHttpConnection<TC> {
     fn connected(&self) -> Connected {
-        Connected::new()
+        let conn = Connected::new();
+        if self.alpn_h2 {
+            conn.negotiated_h2()
+        } else {
+            conn
+        }
     }
 }
 
@@ -158,7 +687,7 @@ impl<TC: TlsConn> AsyncRead for ArtiHttpConnection<TC> {
         buf: &mut ReadBuf<'_>,
     ) -> Poll<Result<(), std::io::Error>> {
         match self.project().inner.project() {
-            MaybeHttpsStreamProj::Http(ds) => ds.as_mut().poll_read(cx, buf),
+            MaybeHttpsStreamProj::Http(rs) => rs.poll_read(cx, buf),
             MaybeHttpsStreamProj::Https(t) => t.poll_read(cx, buf),
         }
     }
@@ -171,21 +700,21 @@ impl<TC: TlsConn> AsyncWrite for ArtiHttpConnection<TC> {
         buf: &[u8],
     ) -> Poll<Result<usize, Error>> {
         match self.project().inner.project() {
-            MaybeHttpsStreamProj::Http(ds) => ds.as_mut().poll_write(cx, buf),
+            MaybeHttpsStreamProj::Http(rs) => rs.poll_write(cx, buf),
             MaybeHttpsStreamProj::Https(t) => t.poll_write(cx, buf),
         }
     }
 
     fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
         match self.project().inner.project() {
-            MaybeHttpsStreamProj::Http(ds) => ds.as_mut().poll_flush(cx),
+            MaybeHttpsStreamProj::Http(rs) => rs.poll_flush(cx),
             MaybeHttpsStreamProj::Https(t) => t.poll_flush(cx),
         }
     }
 
     fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
         match self.project().inner.project() {
-            MaybeHttpsStreamProj::Http(ds) => ds.as_mut().poll_shutdown(cx),
+            MaybeHttpsStreamProj::Http(rs) => rs.poll_shutdown(cx),
             MaybeHttpsStreamProj::Https(t) => t.poll_shutdown(cx),
         }
     }
@@ -239,27 +768,70 @@ impl<R: Runtime, TC: TlsConn> Service<Uri> for ArtiHttpConnector<R, TC> {
         // We use this to avoid the returned future having to borrow `self`.
         let client = self.client.clone();
         let tls_conn = self.tls_conn.clone();
+        let alpn = self.alpn.clone();
+        let tls_config = self.tls_config.clone();
+        let cert_extractor = self.cert_extractor.clone();
+        let proxy_protocol = self.proxy_protocol.clone();
+        let stream_provider = self.stream_provider.clone();
         Box::pin(async move {
             // Extract the host and port to connect to from the URI.
             let (host, port, use_tls) = uri_to_host_port_tls(req)?;
-            // Initiate a new Tor connection, producing a `DataStream` if successful.
-            let addr = (&host as &str, port)
-                .into_tor_addr()
-                .map_err(arti_client::Error::from)?;
-            let ds = client.connect(addr).await?;
+            // Obtain the raw stream to the origin: either from a caller-supplied
+            // provider, or via the default Tor transport.
+            let mut ds = if let Some(provider) = stream_provider.as_ref() {
+                let s = provider(host.clone(), port)
+                    .await
+                    .map_err(|e| ConnectionError::StreamProvider(Arc::new(e)))?;
+                RawStream::Custom(s)
+            } else {
+                // Initiate a new Tor connection, producing a `DataStream` if successful.
+                let addr = (&host as &str, port)
+                    .into_tor_addr()
+                    .map_err(arti_client::Error::from)?;
+                RawStream::Tor(Box::pin(client.connect(addr).await?))
+            };
+
+            // If configured, announce connection metadata to the origin with a
+            // PROXY protocol header before any TLS/HTTP traffic.
+            if let Some(pp) = proxy_protocol.as_ref() {
+                let header = pp.encode();
+                ds.write_all(&header)
+                    .await
+                    .map_err(|e| ConnectionError::ProxyProtocol(Arc::new(e)))?;
+            }
 
+            let mut alpn_h2 = false;
             let inner = match use_tls {
                 UseTls::Tls => {
                     let conn = tls_conn
                         .connect_impl_tls_stream(&host, ds)
                         .await
                         .map_err(|e| ConnectionError::TLS(e.into()))?;
+                    // Check whether the origin selected HTTP/2 via ALPN. We only
+                    // bother asking if we actually advertised some protocols.
+                    if !alpn.is_empty() {
+                        if let Ok(Some(proto)) = conn.get_alpn_protocol() {
+                            alpn_h2 = proto == b"h2";
+                        }
+                    }
+                    // Enforce the certificate pin (if any) now that the
+                    // handshake has completed.
+                    if tls_config.pinned_cert_sha256.is_some() {
+                        let extractor = cert_extractor
+                            .as_ref()
+                            .ok_or(ConnectionError::PinningUnconfigured)?;
+                        if let Some(cert) = extractor(&conn) {
+                            tls_config.check_pin(&host, &cert)?;
+                        } else {
+                            return Err(ConnectionError::CertPinMismatch { host });
+                        }
+                    }
                     MaybeHttpsStream::Https(conn)
                 }
-                UseTls::Bare => MaybeHttpsStream::Http(Box::new(ds).into()),
+                UseTls::Bare => MaybeHttpsStream::Http(ds),
             };
 
-            Ok(ArtiHttpConnection { inner })
+            Ok(ArtiHttpConnection { inner, alpn_h2 })
         })
     }
 }
@@ -0,0 +1,68 @@
+//! Configuration for a channel manager.
+
+use tor_config::{ConfigBuildError, PaddingLevel};
+use tor_units::IntegerMilliseconds;
+
+use derive_builder::Builder;
+use serde::{Deserialize, Serialize};
+
+/// Configuration for a channel manager.
+///
+/// This type is immutable once constructed.  To build one, use
+/// [`ChannelConfigBuilder`], or deserialize one from a string.
+#[derive(Debug, Clone, Builder, Default, Eq, PartialEq)]
+#[builder(build_fn(validate = "Self::validate", error = "ConfigBuildError"))]
+#[builder(derive(Debug, Serialize, Deserialize))]
+#[non_exhaustive]
+pub struct ChannelConfig {
+    /// Control of channel padding.
+    #[builder(default)]
+    pub(crate) padding: PaddingLevel,
+
+    /// Override for the lower bound of the channel padding timing distribution.
+    ///
+    /// When set, this takes precedence over the consensus `nf_ito_low`
+    /// parameter.  When no directory is available it applies only if
+    /// [`padding_timing_high`](ChannelConfig::padding_timing_high) is also
+    /// set; otherwise the compiled-in defaults are used (see
+    /// <https://gitlab.torproject.org/tpo/core/arti/-/issues/528>).
+    #[builder(default)]
+    pub(crate) padding_timing_low: Option<IntegerMilliseconds<u32>>,
+
+    /// Override for the upper bound of the channel padding timing distribution.
+    ///
+    /// As with [`padding_timing_low`](ChannelConfig::padding_timing_low), this
+    /// overrides the consensus `nf_ito_high` parameter.
+    #[builder(default)]
+    pub(crate) padding_timing_high: Option<IntegerMilliseconds<u32>>,
+
+    /// Maximum number of channels the manager may keep open at once.
+    ///
+    /// When opening a new channel would exceed this limit, the least-recently
+    /// used idle channel is evicted.  `None` (the default) means no limit.
+    #[builder(default)]
+    pub(crate) max_channels: Option<usize>,
+}
+
+impl ChannelConfigBuilder {
+    /// Check that this builder will produce a valid configuration.
+    fn validate(&self) -> Result<(), ConfigBuildError> {
+        if let Some(Some(0)) = self.max_channels {
+            return Err(ConfigBuildError::Inconsistent {
+                fields: vec!["max_channels".into()],
+                problem: "must be greater than zero".into(),
+            });
+        }
+        if let (Some(Some(low)), Some(Some(high))) =
+            (&self.padding_timing_low, &self.padding_timing_high)
+        {
+            if low > high {
+                return Err(ConfigBuildError::Inconsistent {
+                    fields: vec!["padding_timing_low".into(), "padding_timing_high".into()],
+                    problem: "low bound exceeds high bound".into(),
+                });
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,22 +1,63 @@
 //! Simple implementation for the internal map state of a ChanMgr.
 
-use std::time::Duration;
+use std::cmp::Reverse;
+use std::time::{Duration, Instant};
 
 use super::{AbstractChannel, Pending};
 use crate::{ChannelConfig, Dormancy, Error, Result};
 
-use std::collections::{hash_map, HashMap};
+use std::collections::BinaryHeap;
+use std::collections::HashMap;
 use std::result::Result as StdResult;
 use std::sync::Arc;
 use tor_config::PaddingLevel;
 use tor_error::{internal, into_internal};
+use tor_cell::chancell::msg::PaddingNegotiate;
+use tor_linkspec::{HasRelayIds, RelayId, RelayIds};
 use tor_netdir::{params::CHANNEL_PADDING_TIMEOUT_UPPER_BOUND, NetDir};
 use tor_proto::channel::padding::Parameters as PaddingParameters;
 use tor_proto::channel::padding::ParametersBuilder as PaddingParametersBuilder;
+use tor_proto::channel::params::ChannelsParamsUpdates;
 use tor_proto::ChannelsParams;
 use tor_units::{BoundedInt32, IntegerMilliseconds};
 use tracing::info;
 
+/// How long to wait before re-checking a map that is over its capacity bound
+/// because every remaining channel is still in use.
+const CAPACITY_RECHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Backoff delay after the first failed channel build to a relay.
+const BACKOFF_BASE: Duration = Duration::from_millis(500);
+
+/// Upper bound on the exponential channel-build backoff delay.
+const BACKOFF_CAP: Duration = Duration::from_secs(300);
+
+/// How long a relay is quarantined after a *permanent* build failure before it
+/// is redialed.
+///
+/// This is deliberately much longer than [`BACKOFF_CAP`]: a permanent failure
+/// (identity mismatch, cert rejection, unreachable address) is unlikely to
+/// clear by retrying soon, so we stop dialing the relay for a good while rather
+/// than spinning on a doomed build.
+const QUARANTINE_WINDOW: Duration = Duration::from_secs(3600);
+
+/// Exponential backoff delay for a relay with `consecutive_failures` failed
+/// channel builds in a row.
+///
+/// The delay doubles with each failure, starting from [`BACKOFF_BASE`] and
+/// saturating at [`BACKOFF_CAP`]; zero failures means no delay.
+fn backoff_delay(consecutive_failures: u32) -> Duration {
+    let Some(shift) = consecutive_failures.checked_sub(1) else {
+        return Duration::ZERO;
+    };
+    let base_ms = BACKOFF_BASE.as_millis() as u64;
+    let delay_ms = 1_u64
+        .checked_shl(shift)
+        .and_then(|factor| base_ms.checked_mul(factor))
+        .unwrap_or(u64::MAX);
+    Duration::from_millis(delay_ms.min(BACKOFF_CAP.as_millis() as u64))
+}
+
 /// A map from channel id to channel state, plus necessary auxiliary state
 ///
 /// We make this a separate type instead of just using
@@ -36,6 +77,27 @@ struct Inner<C: AbstractChannel> {
     /// must never be held while an await is happening.)
     channels: HashMap<C::Ident, ChannelState<C>>,
 
+    /// A secondary index from every relay identity of an `Open`, `Building`
+    /// or `Quarantined` entry to its canonical `C::Ident`.
+    ///
+    /// A channel carries several relay identities (Ed25519 + RSA); this lets
+    /// callers find an existing entry given any subset of them rather than the
+    /// single canonical identity used as the `channels` key.  The invariant is
+    /// that each identity appears here for at most one channel; it is kept in
+    /// sync with `channels` at every mutation point.
+    id_to_channel: HashMap<RelayId, C::Ident>,
+
+    /// Lazy min-heap of candidate expiry deadlines for `Open` channels.
+    ///
+    /// Each time a channel enters the map we push the earliest [`Instant`] at
+    /// which it *could* become eligible for expiry.  The entries are advisory:
+    /// a channel may be used again (moving its real deadline later) or removed
+    /// without its heap entry being updated, so [`ChannelMap::expire_channels`]
+    /// treats every popped entry as a hint and re-checks the live channel
+    /// before acting.  Stale entries (for channels that are gone) and duplicate
+    /// entries (for a channel pushed more than once) are therefore harmless.
+    expiry_deadlines: BinaryHeap<Reverse<Expiry<C::Ident>>>,
+
     /// Parameters for channels that we create, and that all existing channels are using
     ///
     /// Will be updated by a background task, which also notifies all existing
@@ -45,9 +107,34 @@ struct Inner<C: AbstractChannel> {
     /// created using being-replaced parameters, but not get an update.)
     channels_params: ChannelsParams,
 
+    /// Per-relay channel-build failure tracking.
+    ///
+    /// Keyed by the relay's identities, this records how many times in a row a
+    /// channel build to that relay has failed and the earliest instant at which
+    /// it should be dialed again, so a relay whose builds keep failing is not
+    /// retried immediately (see [`backoff_delay`]).  Entries are cleared on the
+    /// next successful build.
+    failures: HashMap<RelayIds, RelayFailure>,
+
+    /// Per-relay (or per-relay-family) channel-padding overrides.
+    ///
+    /// Keyed by a set of relay identities, each entry supplies
+    /// [`PaddingParameters`] that take precedence over the netdir-derived global
+    /// parameters for any channel carrying all of those identities (see
+    /// [`ChannelMap::reconfigure_general`]).  A channel with no matching entry
+    /// falls through to the global behaviour.
+    padding_overrides: HashMap<RelayIds, PaddingParameters>,
+
     /// The configuration (from the config file or API caller)
     config: ChannelConfig,
 
+    /// Maximum number of channels the map is allowed to hold, if any.
+    ///
+    /// When an insertion would push the map above this limit, the
+    /// least-recently-used *idle* channel is evicted (see
+    /// [`Inner::enforce_capacity`]).  `None` means unbounded.
+    capacity: Option<usize>,
+
     /// Dormancy
     dormancy: Dormancy,
 }
@@ -68,12 +155,106 @@ pub(crate) enum ChannelState<C> {
     /// yielding it to the user.
     Open(OpenEntry<C>),
     /// A channel that's getting built.
-    Building(Pending<C>),
+    ///
+    /// The `RelayIds` are the identities the build was dialled for, kept here
+    /// so that an in-flight build is indexed in the secondary map just like an
+    /// `Open` channel: a concurrent request for the same relay should find
+    /// this entry via [`get_by_relay_ids`](ChannelMap::get_by_relay_ids)
+    /// rather than launching a duplicate build.
+    Building(RelayIds, Pending<C>),
     /// A temporary invalid state.
     ///
     /// We insert this into the map temporarily as a placeholder in
     /// `change_state()`.
     Poisoned(Priv),
+    /// A relay that is quarantined after a *permanent* build failure.
+    ///
+    /// No channel exists here: the entry is a tombstone so that
+    /// [`get`](ChannelMap::get) and
+    /// [`get_by_relay_ids`](ChannelMap::get_by_relay_ids) surface the relay as
+    /// unusable — rather than having the caller redial it — until the quarantine
+    /// window elapses and [`expire_channels`](ChannelMap::expire_channels) reaps
+    /// it.
+    Quarantined(QuarantineEntry),
+}
+
+/// The stored state for a relay that is quarantined after a permanent build
+/// failure (see [`ChannelState::Quarantined`]).
+#[derive(Clone)]
+pub(crate) struct QuarantineEntry {
+    /// The relay identities this quarantine covers, kept so the entry can be
+    /// registered in (and removed from) the secondary index like a real channel.
+    ids: Vec<RelayId>,
+    /// When the quarantine lifts and the entry becomes eligible for reaping.
+    until: Instant,
+}
+
+/// The role a channel plays, which governs whether it carries channel padding.
+///
+/// Channels a client opens to carry its own traffic are padded according to the
+/// consensus/config-derived [`PaddingParameters`]; channels between relays must
+/// not be padded at all.  (We don't yet operate as a relay, so in practice every
+/// channel is currently [`ChannelPurpose::UserTraffic`], but the distinction is
+/// applied in [`ChannelMap::reconfigure_general`] so it is correct once we do.)
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum ChannelPurpose {
+    /// A channel this client opened to a relay to carry its own traffic.
+    UserTraffic,
+    /// A channel between two relays, which must not send channel padding.
+    RelayToRelay,
+}
+
+/// The outcome of [`ChannelMap::get_by_relay_ids`], for deciding whether the
+/// caller should dial a new channel to a relay.
+pub(crate) enum ChannelForTarget<C> {
+    /// Found an existing entry: open, mid-build, or quarantined.
+    Found(ChannelState<C>),
+    /// No entry, but the relay is in backoff after a recent transient
+    /// failure; a fresh dial would likely just fail again. The caller should
+    /// defer dialing for (at least) this long instead.
+    Blocked(Duration),
+    /// No entry, and the relay is free to dial.
+    Free,
+}
+
+/// How a channel-build failure should be treated by the failure tracker.
+///
+/// The channel factory classifies the underlying build error into one of these
+/// before handing it to [`ChannelMap::note_build_failure`].  Transient failures
+/// only feed the backoff/scoring logic and leave the relay a normal retry
+/// candidate; permanent failures quarantine it (see [`QUARANTINE_WINDOW`]).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum FailureKind {
+    /// A failure expected to clear on its own — timeout, connection reset,
+    /// network down.
+    Transient,
+    /// A failure unlikely to clear by retrying soon — relay identity mismatch,
+    /// certificate rejection, unreachable address.
+    Permanent,
+}
+
+/// The effective channel-padding state, collapsing dormancy and padding level.
+///
+/// Used in [`ChannelMap::reconfigure_general`] to detect padding *transitions*
+/// so that a STOP/START is only renegotiated with the peer when the state
+/// actually changes.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum PaddingState {
+    /// No padding at all (dormant, or `PaddingLevel::None`).
+    Disabled,
+    /// Reduced padding.
+    Reduced,
+    /// Normal padding.
+    Normal,
+}
+
+/// Recorded channel-build failure state for a single relay.
+#[derive(Clone, Debug)]
+struct RelayFailure {
+    /// Number of consecutive failed channel builds to this relay.
+    consecutive_failures: u32,
+    /// Earliest instant at which the relay should be dialed again.
+    next_retry_at: Instant,
 }
 
 /// An open channel entry.
@@ -83,16 +264,48 @@ pub(crate) struct OpenEntry<C> {
     pub(crate) channel: C,
     /// The maximum unused duration allowed for this channel.
     pub(crate) max_unused_duration: Duration,
+    /// What this channel is for, which controls whether it is padded.
+    pub(crate) purpose: ChannelPurpose,
+}
+
+/// An advisory entry in [`Inner::expiry_deadlines`].
+///
+/// Records the earliest [`Instant`] at which the channel with identity `id`
+/// could expire.  Entries are ordered solely by `deadline`, so the heap behaves
+/// as a min-heap over deadlines regardless of whether `C::Ident` is itself
+/// ordered.
+struct Expiry<I> {
+    /// The earliest instant at which the referenced channel could expire.
+    deadline: Instant,
+    /// Identity of the channel this deadline refers to.
+    id: I,
+}
+
+impl<I> PartialEq for Expiry<I> {
+    fn eq(&self, other: &Self) -> bool {
+        self.deadline == other.deadline
+    }
+}
+impl<I> Eq for Expiry<I> {}
+impl<I> PartialOrd for Expiry<I> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl<I> Ord for Expiry<I> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.deadline.cmp(&other.deadline)
+    }
 }
 
 impl<C: Clone> ChannelState<C> {
     /// Create a new shallow copy of this ChannelState.
-    #[cfg(test)]
     fn clone_ref(&self) -> Result<Self> {
         use ChannelState::*;
         match self {
             Open(ent) => Ok(Open(ent.clone())),
-            Building(pending) => Ok(Building(pending.clone())),
+            Building(ids, pending) => Ok(Building(ids.clone(), pending.clone())),
+            Quarantined(ent) => Ok(Quarantined(ent.clone())),
             Poisoned(_) => Err(Error::Internal(internal!("Poisoned state in channel map"))),
         }
     }
@@ -158,7 +371,7 @@ impl<C: AbstractChannel> ChannelState<C> {
             ChannelState::Poisoned(_) => {
                 Err(Error::Internal(internal!("Poisoned state in channel map")))
             }
-            ChannelState::Building(_) => Ok(()),
+            ChannelState::Building(_, _) | ChannelState::Quarantined(_) => Ok(()),
         }
     }
 
@@ -187,6 +400,178 @@ impl<C: AbstractChannel> ChannelState<C> {
     }
 }
 
+/// Return all the relay identities carried by a channel state.
+///
+/// `Building` is indexed by the identities it was dialled for, so a
+/// concurrent request for the same relay can find the in-flight build via
+/// [`get_by_relay_ids`](ChannelMap::get_by_relay_ids). `Poisoned` carries no
+/// identities we can index, so it yields an empty list.
+fn state_relay_ids<C: AbstractChannel>(state: &ChannelState<C>) -> Vec<RelayId> {
+    match state {
+        ChannelState::Open(ent) => ent.channel.identities().map(|id| id.to_owned()).collect(),
+        ChannelState::Building(ids, _) => ids.identities().map(|id| id.to_owned()).collect(),
+        ChannelState::Quarantined(ent) => ent.ids.clone(),
+        ChannelState::Poisoned(_) => Vec::new(),
+    }
+}
+
+impl<C: AbstractChannel> Inner<C> {
+    /// Return an error unless every identity in `ids` is either unmapped or
+    /// already mapped to `ident` in the secondary index.
+    fn check_ids_free(&self, ident: &C::Ident, ids: &[RelayId]) -> Result<()> {
+        for id in ids {
+            if let Some(existing) = self.id_to_channel.get(id) {
+                if existing != ident {
+                    return Err(Error::Internal(internal!(
+                        "relay identity already mapped to a different channel"
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove all secondary-index entries listed in `ids` that point at
+    /// `ident`.
+    fn deregister_ids(&mut self, ident: &C::Ident, ids: &[RelayId]) {
+        for id in ids {
+            if self.id_to_channel.get(id) == Some(ident) {
+                self.id_to_channel.remove(id);
+            }
+        }
+    }
+
+    /// Register every identity in `ids` as pointing at `ident`.
+    fn register_ids(&mut self, ident: &C::Ident, ids: Vec<RelayId>) {
+        for id in ids {
+            self.id_to_channel.insert(id, ident.clone());
+        }
+    }
+
+    /// Whether the map currently holds more channels than its configured
+    /// capacity allows.  Always `false` when no capacity is set.
+    fn over_capacity(&self) -> bool {
+        matches!(self.capacity, Some(cap) if self.channels.len() > cap)
+    }
+
+    /// Evict least-recently-used idle channels until the map is within its
+    /// configured [`capacity`](Inner::capacity), if one is set.
+    ///
+    /// Only `Open` channels that are currently unused (no outstanding circuits)
+    /// are eligible; channels with live circuits and `Building`/`Pending`
+    /// entries are never evicted, and neither are channels whose
+    /// `duration_unused` is `None` (which includes closed channels).  Among the
+    /// eligible channels the one idle for the longest is removed first.  If the
+    /// map is over capacity but nothing is evictable, we leave it over capacity
+    /// rather than dropping an in-use channel.
+    fn enforce_capacity(&mut self) {
+        let Some(capacity) = self.capacity else {
+            return;
+        };
+        while self.channels.len() > capacity {
+            let Some(victim) = self.lru_idle_channel() else {
+                // Everything left is in use or still building; nothing to evict.
+                break;
+            };
+            if let Some(old) = self.channels.remove(&victim) {
+                let ids = state_relay_ids(&old);
+                self.deregister_ids(&victim, &ids);
+            }
+        }
+    }
+
+    /// Identity of the idle `Open` channel that has been unused for the longest,
+    /// or `None` if the map holds no evictable channel.
+    fn lru_idle_channel(&self) -> Option<C::Ident> {
+        self.channels
+            .iter()
+            .filter_map(|(ident, state)| match state {
+                ChannelState::Open(ent) => {
+                    ent.channel.duration_unused().map(|unused| (ident, unused))
+                }
+                ChannelState::Building(_, _)
+                | ChannelState::Poisoned(_)
+                | ChannelState::Quarantined(_) => None,
+            })
+            .max_by_key(|(_, unused)| *unused)
+            .map(|(ident, _)| ident.clone())
+    }
+
+    /// Push an advisory expiry deadline onto the lazy expiry heap for the
+    /// channel currently stored at `ident`, if it is an `Open` channel.
+    ///
+    /// Non-`Open` states (and absent entries) have nothing to expire and are
+    /// ignored.
+    fn note_expiry(&mut self, ident: &C::Ident) {
+        let entry = self
+            .channels
+            .get(ident)
+            .and_then(|state| expiry_entry(Instant::now(), ident, state));
+        if let Some(entry) = entry {
+            self.expiry_deadlines.push(Reverse(entry));
+        }
+    }
+
+    /// Record a failed channel build to the relay identified by `ids`,
+    /// extending that relay's exponential backoff.
+    ///
+    /// The failure count is incremented and `next_retry_at` is pushed out by
+    /// [`backoff_delay`] of the new count, so repeated failures redial the relay
+    /// ever more slowly up to [`BACKOFF_CAP`].
+    fn note_build_failure(&mut self, ids: RelayIds, now: Instant) {
+        let entry = self.failures.entry(ids).or_insert(RelayFailure {
+            consecutive_failures: 0,
+            next_retry_at: now,
+        });
+        entry.consecutive_failures = entry.consecutive_failures.saturating_add(1);
+        entry.next_retry_at = now
+            .checked_add(backoff_delay(entry.consecutive_failures))
+            .unwrap_or(now);
+    }
+
+    /// The remaining backoff delay before the relay identified by `ids` may be
+    /// dialed again, or `None` if it is retriable now.
+    fn retry_blocked_for(&self, ids: &RelayIds, now: Instant) -> Option<Duration> {
+        self.failures
+            .get(ids)
+            .and_then(|f| f.next_retry_at.checked_duration_since(now))
+            .filter(|d| !d.is_zero())
+    }
+}
+
+/// Build the advisory expiry-heap entry for `state`, or `None` if it is not an
+/// `Open` channel (or if the deadline arithmetic would overflow).
+fn expiry_entry<C: AbstractChannel>(
+    now: Instant,
+    ident: &C::Ident,
+    state: &ChannelState<C>,
+) -> Option<Expiry<C::Ident>> {
+    if let ChannelState::Open(ent) = state {
+        let deadline = open_expiry_instant(ent, now)?;
+        Some(Expiry {
+            deadline,
+            id: ident.clone(),
+        })
+    } else {
+        None
+    }
+}
+
+/// The earliest instant at which `ent` could become eligible for expiry.
+///
+/// An idle channel could expire once the rest of its `max_unused_duration` has
+/// elapsed; a channel that is already overdue yields `now`.  An in-use channel
+/// cannot expire until it has been idle for the full `max_unused_duration`, so
+/// we bound it at `now + max_unused_duration`.  Returns `None` only if the
+/// instant arithmetic would overflow.
+fn open_expiry_instant<C: AbstractChannel>(ent: &OpenEntry<C>, now: Instant) -> Option<Instant> {
+    let remaining = match ent.channel.duration_unused() {
+        Some(unused) => ent.max_unused_duration.saturating_sub(unused),
+        None => ent.max_unused_duration,
+    };
+    now.checked_add(remaining)
+}
+
 impl<C: AbstractChannel> ChannelMap<C> {
     /// Create a new empty ChannelMap.
     pub(crate) fn new(config: ChannelConfig, dormancy: Dormancy) -> Self {
@@ -194,6 +579,11 @@ impl<C: AbstractChannel> ChannelMap<C> {
         ChannelMap {
             inner: std::sync::Mutex::new(Inner {
                 channels: HashMap::new(),
+                id_to_channel: HashMap::new(),
+                expiry_deadlines: BinaryHeap::new(),
+                failures: HashMap::new(),
+                padding_overrides: HashMap::new(),
+                capacity: config.max_channels,
                 config,
                 channels_params,
                 dormancy,
@@ -201,6 +591,26 @@ impl<C: AbstractChannel> ChannelMap<C> {
         }
     }
 
+    /// Set the maximum number of channels the map may hold (for tests).
+    #[cfg(test)]
+    pub(crate) fn set_capacity(&self, capacity: Option<usize>) {
+        let mut inner = self.inner.lock().expect("Poisoned lock");
+        inner.capacity = capacity;
+        inner.enforce_capacity();
+    }
+
+    /// Bring a relay's recorded `next_retry_at` forward by `by` (for tests), so
+    /// that backoff expiry can be exercised without waiting in real time.
+    #[cfg(test)]
+    pub(crate) fn rewind_retry(&self, ids: &impl HasRelayIds, by: Duration) {
+        let mut inner = self.inner.lock().expect("Poisoned lock");
+        if let Some(entry) = inner.failures.get_mut(&RelayIds::from_relay_ids(ids)) {
+            if let Some(when) = entry.next_retry_at.checked_sub(by) {
+                entry.next_retry_at = when;
+            }
+        }
+    }
+
     /// Return the channel state for the given identity, if any.
     #[cfg(test)]
     pub(crate) fn get(&self, ident: &C::Ident) -> Result<Option<ChannelState<C>>> {
@@ -212,6 +622,148 @@ impl<C: AbstractChannel> ChannelMap<C> {
             .transpose()
     }
 
+    /// Look up a channel (or in-flight build) for a relay carrying any of the
+    /// relay identities in `ids`, to decide whether the caller should dial a
+    /// new channel.
+    ///
+    /// All of a channel's identities map to the same entry in the secondary
+    /// index, so the first recognized identity is enough to find it. If no
+    /// entry is found, this also consults [`Self::retry_blocked_for`] so a
+    /// relay in backoff after a recent transient failure is reported as
+    /// [`ChannelForTarget::Blocked`] rather than silently inviting another
+    /// doomed dial.
+    pub(crate) fn get_by_relay_ids(
+        &self,
+        ids: &impl HasRelayIds,
+    ) -> Result<ChannelForTarget<C>>
+    where
+        C: Clone,
+    {
+        {
+            let inner = self.inner.lock()?;
+            for id in ids.identities() {
+                if let Some(ident) = inner.id_to_channel.get(&id.to_owned()) {
+                    if let Some(state) = inner
+                        .channels
+                        .get(ident)
+                        .map(ChannelState::clone_ref)
+                        .transpose()?
+                    {
+                        return Ok(ChannelForTarget::Found(state));
+                    }
+                }
+            }
+        }
+        Ok(match self.retry_blocked_for(ids)? {
+            Some(delay) => ChannelForTarget::Blocked(delay),
+            None => ChannelForTarget::Free,
+        })
+    }
+
+    /// Record that a channel build to the relay identified by `ids` has failed,
+    /// classified by `kind`.
+    ///
+    /// The channel-request path calls this whenever a build attempt errors out.
+    /// A [`FailureKind::Transient`] failure extends that relay's exponential
+    /// backoff so it is not retried immediately; a [`FailureKind::Permanent`]
+    /// failure quarantines the relay under `ident` for [`QUARANTINE_WINDOW`], so
+    /// `get`/`get_by_relay_ids` report it as unusable rather than redialing it.
+    /// Both are cleared by [`note_build_success`](Self::note_build_success).
+    pub(crate) fn note_build_failure(
+        &self,
+        ident: C::Ident,
+        ids: &impl HasRelayIds,
+        kind: FailureKind,
+    ) -> Result<()> {
+        let now = Instant::now();
+        let mut inner = self.inner.lock()?;
+        match kind {
+            FailureKind::Transient => {
+                inner.note_build_failure(RelayIds::from_relay_ids(ids), now);
+            }
+            FailureKind::Permanent => {
+                // A permanent failure supersedes any transient backoff: the
+                // quarantine tombstone governs retry eligibility from here.
+                inner.failures.remove(&RelayIds::from_relay_ids(ids));
+                let relay_ids: Vec<RelayId> = ids.identities().map(|id| id.to_owned()).collect();
+                inner.check_ids_free(&ident, &relay_ids)?;
+                let entry = QuarantineEntry {
+                    ids: relay_ids.clone(),
+                    until: now
+                        .checked_add(QUARANTINE_WINDOW)
+                        .unwrap_or(now),
+                };
+                let old = inner
+                    .channels
+                    .insert(ident.clone(), ChannelState::Quarantined(entry));
+                if let Some(old) = &old {
+                    let old_ids = state_relay_ids(old);
+                    inner.deregister_ids(&ident, &old_ids);
+                }
+                inner.register_ids(&ident, relay_ids);
+            }
+        }
+        Ok(())
+    }
+
+    /// Record that a channel build to the relay identified by `ids` has
+    /// succeeded, clearing any recorded failure backoff or quarantine.
+    pub(crate) fn note_build_success(&self, ids: &impl HasRelayIds) -> Result<()> {
+        let mut inner = self.inner.lock()?;
+        inner.failures.remove(&RelayIds::from_relay_ids(ids));
+        // Drop any quarantine tombstone covering these identities.
+        let quarantined: Option<C::Ident> = ids
+            .identities()
+            .find_map(|id| inner.id_to_channel.get(&id.to_owned()).cloned())
+            .filter(|ident| matches!(inner.channels.get(ident), Some(ChannelState::Quarantined(_))));
+        if let Some(ident) = quarantined {
+            if let Some(old) = inner.channels.remove(&ident) {
+                let old_ids = state_relay_ids(&old);
+                inner.deregister_ids(&ident, &old_ids);
+            }
+        }
+        Ok(())
+    }
+
+    /// Return the remaining backoff delay before a fresh channel build to the
+    /// relay identified by `ids` may be attempted, or `None` if it is retriable
+    /// now.
+    ///
+    /// Consulted by [`Self::get_by_relay_ids`] so the channel-request path
+    /// gets the "not yet retriable" signal without a separate call: a `Some`
+    /// value means a build would just fail again and should be deferred
+    /// until the delay has elapsed.
+    pub(crate) fn retry_blocked_for(&self, ids: &impl HasRelayIds) -> Result<Option<Duration>> {
+        let inner = self.inner.lock()?;
+        Ok(inner.retry_blocked_for(&RelayIds::from_relay_ids(ids), Instant::now()))
+    }
+
+    /// Register (or, with `params` of `None`, clear) a channel-padding override
+    /// for the relay or relay-family identified by `ids`.
+    ///
+    /// The override takes precedence over the netdir-derived global parameters
+    /// for every channel carrying all of `ids`, letting an operator disable or
+    /// tighten padding for specific bridges/guards without changing global
+    /// policy.  It takes effect at the next
+    /// [`reconfigure_general`](Self::reconfigure_general).
+    pub(crate) fn set_padding_override(
+        &self,
+        ids: &impl HasRelayIds,
+        params: Option<PaddingParameters>,
+    ) -> Result<()> {
+        let key = RelayIds::from_relay_ids(ids);
+        let mut inner = self.inner.lock()?;
+        match params {
+            Some(params) => {
+                inner.padding_overrides.insert(key, params);
+            }
+            None => {
+                inner.padding_overrides.remove(&key);
+            }
+        }
+        Ok(())
+    }
+
     /// Replace the channel state for `ident` with `newval`, and return the
     /// previous value if any.
     #[cfg(test)]
@@ -222,7 +774,17 @@ impl<C: AbstractChannel> ChannelMap<C> {
     ) -> Result<Option<ChannelState<C>>> {
         newval.check_ident(&ident)?;
         let mut inner = self.inner.lock()?;
-        Ok(inner.channels.insert(ident, newval))
+        let new_ids = state_relay_ids(&newval);
+        inner.check_ids_free(&ident, &new_ids)?;
+        let old = inner.channels.insert(ident.clone(), newval);
+        if let Some(old) = &old {
+            let old_ids = state_relay_ids(old);
+            inner.deregister_ids(&ident, &old_ids);
+        }
+        inner.register_ids(&ident, new_ids);
+        inner.note_expiry(&ident);
+        inner.enforce_capacity();
+        Ok(old)
     }
 
     /// Replace the channel state for `ident` with the return value from `func`,
@@ -243,24 +805,50 @@ impl<C: AbstractChannel> ChannelMap<C> {
         let mut inner = self.inner.lock()?;
         let newval = func(&inner.channels_params)?;
         newval.check_ident(&ident)?;
-        Ok(inner.channels.insert(ident, newval))
+        let new_ids = state_relay_ids(&newval);
+        inner.check_ids_free(&ident, &new_ids)?;
+        let old = inner.channels.insert(ident.clone(), newval);
+        if let Some(old) = &old {
+            let old_ids = state_relay_ids(old);
+            inner.deregister_ids(&ident, &old_ids);
+        }
+        inner.register_ids(&ident, new_ids);
+        inner.note_expiry(&ident);
+        inner.enforce_capacity();
+        Ok(old)
     }
 
     /// Remove and return the state for `ident`, if any.
     pub(crate) fn remove(&self, ident: &C::Ident) -> Result<Option<ChannelState<C>>> {
         let mut inner = self.inner.lock()?;
-        Ok(inner.channels.remove(ident))
+        let old = inner.channels.remove(ident);
+        if let Some(old) = &old {
+            let old_ids = state_relay_ids(old);
+            inner.deregister_ids(ident, &old_ids);
+        }
+        Ok(old)
     }
 
     /// Remove every unusable state from the map.
     #[cfg(test)]
     pub(crate) fn remove_unusable(&self) -> Result<()> {
         let mut inner = self.inner.lock()?;
-        inner.channels.retain(|_, state| match state {
-            ChannelState::Poisoned(_) => false,
-            ChannelState::Open(ent) => ent.channel.is_usable(),
-            ChannelState::Building(_) => true,
+        let inner = &mut *inner;
+        let mut removed = Vec::new();
+        inner.channels.retain(|ident, state| {
+            let keep = match state {
+                ChannelState::Poisoned(_) => false,
+                ChannelState::Open(ent) => ent.channel.is_usable(),
+                ChannelState::Building(_, _) | ChannelState::Quarantined(_) => true,
+            };
+            if !keep {
+                removed.push((ident.clone(), state_relay_ids(state)));
+            }
+            keep
         });
+        for (ident, ids) in removed {
+            inner.deregister_ids(&ident, &ids);
+        }
         Ok(())
     }
 
@@ -281,36 +869,59 @@ impl<C: AbstractChannel> ChannelMap<C> {
     pub(crate) fn change_state<F, V>(&self, ident: &C::Ident, func: F) -> Result<V>
     where
         F: FnOnce(Option<ChannelState<C>>) -> (Option<ChannelState<C>>, V),
+        C: Clone,
     {
-        use hash_map::Entry::*;
         let mut inner = self.inner.lock()?;
-        let entry = inner.channels.entry(ident.clone());
-        match entry {
-            Occupied(mut occupied) => {
-                // Temporarily replace the entry for this identity with
-                // a poisoned entry.
-                let mut oldent = ChannelState::Poisoned(Priv { _unused: () });
-                std::mem::swap(occupied.get_mut(), &mut oldent);
-                let (newval, output) = func(Some(oldent));
-                match newval {
-                    Some(mut newent) => {
-                        newent.check_ident(ident)?;
-                        std::mem::swap(occupied.get_mut(), &mut newent);
+        let inner = &mut *inner;
+        if let Some(slot) = inner.channels.get_mut(ident) {
+            // Temporarily replace the entry for this identity with a poisoned
+            // entry while `func` runs.
+            let oldent = std::mem::replace(slot, ChannelState::Poisoned(Priv { _unused: () }));
+            let old_ids = state_relay_ids(&oldent);
+            // Keep a restorable copy: if the new state `func` returns turns
+            // out to be invalid, we put this back instead of leaving the
+            // slot poisoned (which would corrupt this identity for good).
+            let restore = oldent.clone_ref();
+            let (newval, output) = func(Some(oldent));
+            match newval {
+                Some(newent) => {
+                    let new_ids = state_relay_ids(&newent);
+                    let validated = newent
+                        .check_ident(ident)
+                        .and_then(|()| inner.check_ids_free(ident, &new_ids));
+                    if let Err(e) = validated {
+                        if let Some(slot) = inner.channels.get_mut(ident) {
+                            *slot = restore?;
+                        }
+                        return Err(e);
                     }
-                    None => {
-                        occupied.remove();
-                    }
-                };
-                Ok(output)
-            }
-            Vacant(vacant) => {
-                let (newval, output) = func(None);
-                if let Some(newent) = newval {
-                    newent.check_ident(ident)?;
-                    vacant.insert(newent);
+                    // Swapping in a channel with different ids must atomically
+                    // move the secondary index across.
+                    *inner
+                        .channels
+                        .get_mut(ident)
+                        .expect("channel slot vanished") = newent;
+                    inner.deregister_ids(ident, &old_ids);
+                    inner.register_ids(ident, new_ids);
+                    inner.note_expiry(ident);
                 }
-                Ok(output)
+                None => {
+                    inner.channels.remove(ident);
+                    inner.deregister_ids(ident, &old_ids);
+                }
+            };
+            Ok(output)
+        } else {
+            let (newval, output) = func(None);
+            if let Some(newent) = newval {
+                newent.check_ident(ident)?;
+                let new_ids = state_relay_ids(&newent);
+                inner.check_ids_free(ident, &new_ids)?;
+                inner.channels.insert(ident.clone(), newent);
+                inner.register_ids(ident, new_ids);
+                inner.note_expiry(ident);
             }
+            Ok(output)
         }
     }
 
@@ -331,10 +942,6 @@ impl<C: AbstractChannel> ChannelMap<C> {
     ) -> StdResult<(), tor_error::Bug> {
         use ChannelState as CS;
 
-        // TODO support dormant mode
-        // TODO when entering/leaving dormant mode, send CELL_PADDING_NEGOTIATE to peers
-        // TODO with reduced padding, send CELL_PADDING_NEGOTIATE
-
         // TODO when we support operation as a relay, inter-relay channels ought
         // not to get padding.
         let netdir = {
@@ -353,36 +960,131 @@ impl<C: AbstractChannel> ChannelMap<C> {
             .lock()
             .map_err(|_| internal!("poisonned channel manager"))?;
 
+        // Remember the previous dormancy and padding level so we can detect
+        // transitions (and only renegotiate padding when one actually occurs).
+        let prev_dormancy = inner.dormancy;
+        let prev_padding = inner.config.padding;
+
         if let Some(new_config) = new_config {
             inner.config = new_config.clone();
+            inner.capacity = inner.config.max_channels;
         }
         if let Some(new_dormancy) = new_dormancy {
             inner.dormancy = new_dormancy;
         }
 
-        let padding_parameters = padding_parameters(inner.config.padding, netdir.as_ref())?;
-        // TODO if this is equal to all_zeroes(), do not enable padding
-        // (when we enable padding at all, which we do not do yet...)
+        let was_dormant = matches!(prev_dormancy, Dormancy::Dormant);
+        let now_dormant = matches!(inner.dormancy, Dormancy::Dormant);
+
+        // When dormant, we send no padding at all; otherwise we derive the
+        // padding parameters from the config and (if available) the netdir.
+        let padding_parameters = if now_dormant {
+            PaddingParameters::all_zeroes()
+        } else {
+            padding_parameters(
+                inner.config.padding,
+                inner.config.padding_timing_low,
+                inner.config.padding_timing_high,
+                netdir.as_ref(),
+            )?
+        };
+
+        // We only renegotiate padding with the far end when the effective
+        // padding state actually changes: emitting a STOP/START on every
+        // reconfiguration (e.g. an unrelated netdir refresh) would spam cells to
+        // every open channel.  We collapse dormancy and padding level into a
+        // single state, then compare the previous state with the new one.
+        let effective_state = |dormant: bool, padding: PaddingLevel| {
+            if dormant || padding == PaddingLevel::None {
+                PaddingState::Disabled
+            } else if padding == PaddingLevel::Reduced {
+                PaddingState::Reduced
+            } else {
+                PaddingState::Normal
+            }
+        };
+        let prev_state = effective_state(was_dormant, prev_padding);
+        let now_state = effective_state(now_dormant, inner.config.padding);
+        let negotiate = match (prev_state, now_state) {
+            // No change in padding state: nothing to renegotiate.
+            (prev, now) if prev == now => None,
+            // Entering a padding-disabled state: tell the peer to stop.
+            (_, PaddingState::Disabled) => Some(PaddingNegotiate::stop()),
+            // Resuming padding, or switching between Normal and Reduced: tell
+            // the peer to (re)start padding with its default parameters.
+            _ => Some(PaddingNegotiate::start_default()),
+        };
 
         let update = inner
             .channels_params
             .start_update()
             .padding_parameters(padding_parameters)
-            .finish();
-        let update = if let Some(u) = update {
-            u
+            .finish()
+            .map(Arc::new);
+
+        // Inter-relay channels must never send channel padding, regardless of
+        // the consensus/config-derived timings we use for client traffic.  We
+        // only bother computing the zero-padding update when such a channel is
+        // actually present, so the common client-only case keeps its fast path.
+        let have_relay_channel = inner.channels.values().any(|state| {
+            matches!(
+                state,
+                CS::Open(OpenEntry {
+                    purpose: ChannelPurpose::RelayToRelay,
+                    ..
+                })
+            )
+        });
+        let relay_update = if have_relay_channel {
+            ChannelsParams::default()
+                .start_update()
+                .padding_parameters(PaddingParameters::all_zeroes())
+                .finish()
+                .map(Arc::new)
         } else {
-            return Ok(());
+            None
         };
-        let update = Arc::new(update);
+
+        // Per-relay padding overrides are applied channel-by-channel below, so
+        // their presence also means there is work to do even if the global
+        // parameters are unchanged.  Snapshot them before we borrow `channels`
+        // mutably in the loop.
+        let padding_overrides = inner.padding_overrides.clone();
+
+        if update.is_none()
+            && relay_update.is_none()
+            && negotiate.is_none()
+            && padding_overrides.is_empty()
+        {
+            return Ok(());
+        }
 
         for channel in inner.channels.values_mut() {
-            let channel = match channel {
-                CS::Open(OpenEntry { channel, .. }) => channel,
-                CS::Building(_) | CS::Poisoned(_) => continue,
+            let (channel, purpose) = match channel {
+                CS::Open(OpenEntry {
+                    channel, purpose, ..
+                }) => (channel, *purpose),
+                CS::Building(_, _) | CS::Poisoned(_) | CS::Quarantined(_) => continue,
+            };
+            // A registered override for this relay wins over the global update;
+            // inter-relay channels get the zero-padding update; all other client
+            // channels get the consensus/config-derived one.
+            let overridden = padding_override_for(&padding_overrides, channel).map(override_update);
+            let chan_update = match (purpose, &overridden) {
+                (ChannelPurpose::UserTraffic, Some(overridden)) => overridden,
+                (ChannelPurpose::RelayToRelay, _) => &relay_update,
+                (ChannelPurpose::UserTraffic, None) => &update,
             };
-            // Ignore error (which simply means the channel is closed or gone)
-            let _ = channel.reparameterize(update.clone());
+            // Ignore errors (which simply mean the channel is closed or gone).
+            if let Some(update) = chan_update {
+                let _ = channel.reparameterize(update.clone());
+            }
+            // Padding renegotiation only applies to padded (client) channels.
+            if purpose == ChannelPurpose::UserTraffic {
+                if let Some(negotiate) = &negotiate {
+                    let _ = channel.send_padding_negotiate(negotiate.clone());
+                }
+            }
         }
         Ok(())
     }
@@ -392,22 +1094,124 @@ impl<C: AbstractChannel> ChannelMap<C> {
     /// Return a Duration until the next time at which
     /// a channel _could_ expire.
     pub(crate) fn expire_channels(&self) -> Duration {
-        let mut ret = Duration::from_secs(180);
-        self.inner
-            .lock()
-            .expect("Poisoned lock")
-            .channels
-            .retain(|_id, chan| !chan.ready_to_expire(&mut ret));
-        ret
+        let now = Instant::now();
+        let mut inner = self.inner.lock().expect("Poisoned lock");
+        let inner = &mut *inner;
+
+        // Pop every entry whose advisory deadline has already passed.  Each
+        // popped id is re-checked against its live channel: a channel that is
+        // gone leaves a stale entry we simply drop, one that is now in use or
+        // was refreshed is reinserted with its recomputed deadline (lazy
+        // invalidation), and one that really is overdue is expired.
+        let mut expired = Vec::new();
+        let mut reinsert = Vec::new();
+        while let Some(Reverse(entry)) = inner.expiry_deadlines.peek() {
+            if entry.deadline > now {
+                break;
+            }
+            let Reverse(Expiry { id, .. }) = inner
+                .expiry_deadlines
+                .pop()
+                .expect("heap emptied between peek and pop");
+            let Some(state) = inner.channels.get(&id) else {
+                // Stale entry for a channel that has since been removed.
+                continue;
+            };
+            let mut unused = Duration::MAX;
+            if state.ready_to_expire(&mut unused) {
+                expired.push((id.clone(), state_relay_ids(state)));
+            } else if let Some(entry) = expiry_entry(now, &id, state) {
+                // Not ready yet (in use or refreshed): remember the recomputed
+                // deadline and reinsert it once we have finished draining, so a
+                // deadline that is still due now cannot spin this loop.
+                reinsert.push(entry);
+            }
+        }
+        for entry in reinsert {
+            inner.expiry_deadlines.push(Reverse(entry));
+        }
+        for (ident, ids) in &expired {
+            inner.channels.remove(ident);
+            inner.deregister_ids(ident, ids);
+        }
+
+        // Reap quarantine tombstones whose window has elapsed, and remember the
+        // soonest one still pending so it can feed the wakeup schedule below.
+        let mut quarantine_expired = Vec::new();
+        let mut next_quarantine: Option<Duration> = None;
+        for (ident, state) in inner.channels.iter() {
+            if let ChannelState::Quarantined(ent) = state {
+                match ent.until.checked_duration_since(now) {
+                    None => quarantine_expired.push((ident.clone(), ent.ids.clone())),
+                    Some(remaining) => {
+                        next_quarantine = Some(match next_quarantine {
+                            Some(d) => d.min(remaining),
+                            None => remaining,
+                        });
+                    }
+                }
+            }
+        }
+        for (ident, ids) in &quarantine_expired {
+            inner.channels.remove(ident);
+            inner.deregister_ids(ident, ids);
+        }
+
+        // Besides the time bound, the map also has a capacity bound: reaping may
+        // have freed room, but a shrunk capacity (after reconfiguration) can
+        // also leave us over the limit, so re-run the LRU eviction here too.
+        inner.enforce_capacity();
+
+        // The next time a channel _could_ expire is when the new heap minimum
+        // comes due; with an empty heap we fall back to the historical floor.
+        let next_expiry = match inner.expiry_deadlines.peek() {
+            Some(Reverse(entry)) => entry.deadline.saturating_duration_since(now),
+            None => Duration::from_secs(180),
+        };
+
+        // A relay in backoff also needs a wakeup: once its `next_retry_at`
+        // comes due it becomes retriable again, so fold the soonest pending
+        // retry instant into the same schedule.
+        let mut wakeup = next_expiry;
+        if let Some(next_retry) = inner
+            .failures
+            .values()
+            .filter_map(|f| f.next_retry_at.checked_duration_since(now))
+            .min()
+        {
+            wakeup = wakeup.min(next_retry);
+        }
+        // Likewise, a quarantine tombstone needs a wakeup when its window lifts.
+        if let Some(next_quarantine) = next_quarantine {
+            wakeup = wakeup.min(next_quarantine);
+        }
+
+        // If we are still over capacity, every evictable channel is already
+        // gone and only in-use channels remain; one of them could fall idle (and
+        // so become evictable) at any moment, so ask to be polled again sooner
+        // than the next time-based deadline.
+        if inner.over_capacity() {
+            wakeup.min(CAPACITY_RECHECK_INTERVAL)
+        } else {
+            wakeup
+        }
     }
 }
 
 /// Given a `NetDirExtract` and whether we're reducing padding, return a `PaddingParameters`
 ///
+/// The `low`/`high` arguments are the operator-specified timing overrides from
+/// [`ChannelConfig`]: when present they take precedence over the consensus
+/// `nf_ito_*` parameters (and, when no netdir is available and both are
+/// present, over the compiled-in defaults), as discussed in
+/// <https://gitlab.torproject.org/tpo/core/arti/-/issues/528>.
+///
 /// With `PaddingLevel::None`, will return `PaddingParameters::all_zeroes`; but
 /// does not account for padding being enabled/disabled other ways than via the config.
 fn padding_parameters(
     config: PaddingLevel,
+    low: Option<IntegerMilliseconds<u32>>,
+    high: Option<IntegerMilliseconds<u32>>,
     netdir: StdResult<&NetDirExtract, &()>,
 ) -> StdResult<PaddingParameters, tor_error::Bug> {
     let reduced = match config {
@@ -423,8 +1227,16 @@ fn padding_parameters(
                 let nf_ito = netdir.nf_ito[usize::from(reduced)];
                 let get_timing_param =
                     |index: usize| nf_ito[index].try_map(|bounded| bounded.get().try_into());
-                let low = get_timing_param(0).map_err(|_| "low value arithmetic overflow?!")?;
-                let high = get_timing_param(1).map_err(|_| "high value arithmetic overflow?!")?;
+                // Start from the consensus values, then let any config override
+                // take precedence.
+                let low = match low {
+                    Some(low) => low,
+                    None => get_timing_param(0).map_err(|_| "low value arithmetic overflow?!")?,
+                };
+                let high = match high {
+                    Some(high) => high,
+                    None => get_timing_param(1).map_err(|_| "high value arithmetic overflow?!")?,
+                };
                 if low > high {
                     return Err("low > high");
                 }
@@ -442,18 +1254,63 @@ fn padding_parameters(
             p.build()
                 .map_err(into_internal!("failed to build padding parameters"))?
         }
-        Err(()) => {
-            // TODO we should use a fallback here so that config overrides take effect,
-            // as discussed in https://gitlab.torproject.org/tpo/core/arti/-/issues/528
-            if reduced {
-                PaddingParameters::default_reduced()
-            } else {
-                PaddingParameters::default()
+        // Without a netdir we have no consensus `nf_ito_*` parameters to read,
+        // but operator-specified timing must still take effect (issue 528).
+        // Use the config overrides when both bounds are supplied; otherwise fall
+        // back to the compiled-in defaults.
+        Err(()) => match (low, high) {
+            (Some(low), Some(high)) if low <= high => {
+                let mut p = PaddingParametersBuilder::default();
+                p.low_ms(low);
+                p.high_ms(high);
+                p.build()
+                    .map_err(into_internal!("failed to build padding parameters"))?
             }
-        }
+            (Some(_low), Some(_high)) => {
+                info!("configured channel padding parameters wrong (low > high), using defaults");
+                fallback_padding_parameters(reduced)
+            }
+            _ => fallback_padding_parameters(reduced),
+        },
     })
 }
 
+/// Return the registered padding override that applies to `channel`, if any.
+///
+/// A table entry applies when `channel` carries *all* of the entry's relay
+/// identities, so a single-identity or whole-family key both match the channels
+/// they name.
+fn padding_override_for<'a, C: HasRelayIds>(
+    overrides: &'a HashMap<RelayIds, PaddingParameters>,
+    channel: &C,
+) -> Option<&'a PaddingParameters> {
+    overrides
+        .iter()
+        .find_map(|(ids, params)| channel.has_all_relay_ids_from(ids).then_some(params))
+}
+
+/// Build the per-channel [`ChannelsParamsUpdates`] that installs `params`,
+/// independent of the global channel parameters.
+///
+/// Returns `None` only when `params` already matches the defaults and so needs
+/// no update.
+fn override_update(params: &PaddingParameters) -> Option<Arc<ChannelsParamsUpdates>> {
+    ChannelsParams::default()
+        .start_update()
+        .padding_parameters(params.clone())
+        .finish()
+        .map(Arc::new)
+}
+
+/// The compiled-in default padding parameters for the given padding level.
+fn fallback_padding_parameters(reduced: bool) -> PaddingParameters {
+    if reduced {
+        PaddingParameters::default_reduced()
+    } else {
+        PaddingParameters::default()
+    }
+}
+
 #[cfg(test)]
 mod test {
     // @@ begin test lint list maintained by maint/add_warning @@
@@ -467,9 +1324,18 @@ mod test {
 
     use super::*;
     use std::sync::Arc;
-    use tor_proto::channel::params::ChannelsParamsUpdates;
+    use tor_linkspec::{RelayIdRef, RelayIdType};
+    use tor_llcrypto::pk::ed25519::Ed25519Identity;
     use tor_proto::channel::ChannelUsage;
 
+    /// Build a deterministic fake Ed25519 identity from a channel nickname, so
+    /// that distinct nicknames get distinct relay identities.
+    fn fake_ed(ident: &str) -> Ed25519Identity {
+        let mut bytes = [0_u8; 32];
+        bytes[0] = ident.as_bytes()[0];
+        bytes.into()
+    }
+
     fn new_test_channel_map<C: AbstractChannel>() -> ChannelMap<C> {
         ChannelMap::new(ChannelConfig::default(), Default::default())
     }
@@ -480,6 +1346,16 @@ mod test {
         usable: bool,
         unused_duration: Option<u64>,
         params_update: Option<Arc<ChannelsParamsUpdates>>,
+        padding_negotiate: Option<PaddingNegotiate>,
+        ed_ident: Ed25519Identity,
+    }
+    impl HasRelayIds for FakeChannel {
+        fn identity(&self, key_type: RelayIdType) -> Option<RelayIdRef<'_>> {
+            match key_type {
+                RelayIdType::Ed25519 => Some((&self.ed_ident).into()),
+                _ => None,
+            }
+        }
     }
     impl AbstractChannel for FakeChannel {
         type Ident = u8;
@@ -496,6 +1372,13 @@ mod test {
             self.params_update = Some(update);
             Ok(())
         }
+        fn send_padding_negotiate(
+            &mut self,
+            negotiate: PaddingNegotiate,
+        ) -> tor_proto::Result<()> {
+            self.padding_negotiate = Some(negotiate);
+            Ok(())
+        }
         fn note_usage(&self, _usage: ChannelUsage) -> StdResult<(), tor_error::Bug> {
             Ok(())
         }
@@ -506,10 +1389,13 @@ mod test {
             usable: true,
             unused_duration: None,
             params_update: None,
+            padding_negotiate: None,
+            ed_ident: fake_ed(ident),
         };
         ChannelState::Open(OpenEntry {
             channel,
             max_unused_duration: Duration::from_secs(180),
+            purpose: ChannelPurpose::UserTraffic,
         })
     }
     fn ch_with_details(
@@ -522,10 +1408,29 @@ mod test {
             usable: true,
             unused_duration,
             params_update: None,
+            padding_negotiate: None,
+            ed_ident: fake_ed(ident),
         };
         ChannelState::Open(OpenEntry {
             channel,
             max_unused_duration,
+            purpose: ChannelPurpose::UserTraffic,
+        })
+    }
+    /// Construct an open inter-relay channel, which must never be padded.
+    fn relay_ch(ident: &'static str) -> ChannelState<FakeChannel> {
+        let channel = FakeChannel {
+            ident,
+            usable: true,
+            unused_duration: None,
+            params_update: None,
+            padding_negotiate: None,
+            ed_ident: fake_ed(ident),
+        };
+        ChannelState::Open(OpenEntry {
+            channel,
+            max_unused_duration: Duration::from_secs(180),
+            purpose: ChannelPurpose::RelayToRelay,
         })
     }
     fn closed(ident: &'static str) -> ChannelState<FakeChannel> {
@@ -534,10 +1439,13 @@ mod test {
             usable: false,
             unused_duration: None,
             params_update: None,
+            padding_negotiate: None,
+            ed_ident: fake_ed(ident),
         };
         ChannelState::Open(OpenEntry {
             channel,
             max_unused_duration: Duration::from_secs(180),
+            purpose: ChannelPurpose::UserTraffic,
         })
     }
 
@@ -634,10 +1542,14 @@ mod test {
         assert!(matches!(e, Err(Error::Internal(_))));
         assert!(matches!(map.get(&b'P'), Ok(None)));
 
-        // Try replacing Some with invalid entry (mismatched ID)
+        // Try replacing Some with invalid entry (mismatched ID): the slot is
+        // restored to its prior state rather than left poisoned.
         let e = map.change_state(&b'G', |state| (Some(ch("Wobbledy")), (state, "Hi")));
         assert!(matches!(e, Err(Error::Internal(_))));
-        assert!(matches!(map.get(&b'G'), Err(Error::Internal(_))));
+        assert_eq!(
+            map.get(&b'G').unwrap().unwrap().unwrap_open().ident,
+            "Geheimnisse"
+        );
     }
 
     #[test]
@@ -692,6 +1604,273 @@ mod test {
         with_ch(&|ch| assert_eq!(ch.params_update, None));
     }
 
+    #[test]
+    fn relay_channels_are_not_padded() {
+        let map = new_test_channel_map();
+
+        // Pre-set non-default parameters so we can tell when an update happens.
+        let _ = map
+            .inner
+            .lock()
+            .unwrap()
+            .channels_params
+            .start_update()
+            .padding_parameters(
+                PaddingParametersBuilder::default()
+                    .low_ms(1234.into())
+                    .build()
+                    .unwrap(),
+            )
+            .finish();
+
+        // A client channel and a relay channel in the same map.
+        assert!(map.replace(b't', ch("track")).unwrap().is_none());
+        assert!(map.replace(b'r', relay_ch("relay")).unwrap().is_none());
+
+        let netdir = tor_netdir::testnet::construct_netdir()
+            .unwrap_if_sufficient()
+            .unwrap();
+        let netdir = Arc::new(netdir);
+
+        let take_update = |ident: u8| {
+            let mut inner = map.inner.lock().unwrap();
+            let ch = inner.channels.get_mut(&ident).unwrap().unwrap_open();
+            ch.params_update.take()
+        };
+
+        // A single reconfiguration pass gives the client channel the
+        // netdir-derived timings and the relay channel a zero-padding update.
+        map.reconfigure_general(None, None, Ok(netdir)).unwrap();
+
+        assert_eq!(
+            format!("{:?}", take_update(b't').unwrap()),
+            "ChannelsParamsUpdates { padding_enable: None, \
+                padding_parameters: Some(Parameters { \
+                    low_ms: IntegerMilliseconds { value: 1500 }, \
+                    high_ms: IntegerMilliseconds { value: 9500 } }) }"
+        );
+        assert_eq!(
+            format!("{:?}", take_update(b'r').unwrap()),
+            "ChannelsParamsUpdates { padding_enable: None, \
+                padding_parameters: Some(Parameters { \
+                    low_ms: IntegerMilliseconds { value: 0 }, \
+                    high_ms: IntegerMilliseconds { value: 0 } }) }"
+        );
+    }
+
+    #[test]
+    fn per_relay_padding_override() {
+        let map = new_test_channel_map();
+
+        // A channel with an override and one without, in the same map.
+        assert!(map.replace(b't', ch("track")).unwrap().is_none());
+        assert!(map.replace(b'u', ch("unseen")).unwrap().is_none());
+
+        // Register a tightened padding override for `track` only.
+        let override_params = PaddingParametersBuilder::default()
+            .low_ms(42.into())
+            .high_ms(99.into())
+            .build()
+            .unwrap();
+        let track_query = FakeChannel {
+            ident: "track",
+            usable: true,
+            unused_duration: None,
+            params_update: None,
+            padding_negotiate: None,
+            ed_ident: fake_ed("track"),
+        };
+        map.set_padding_override(&track_query, Some(override_params))
+            .unwrap();
+
+        let netdir = tor_netdir::testnet::construct_netdir()
+            .unwrap_if_sufficient()
+            .unwrap();
+        let netdir = Arc::new(netdir);
+
+        let take_update = |ident: u8| {
+            let mut inner = map.inner.lock().unwrap();
+            let ch = inner.channels.get_mut(&ident).unwrap().unwrap_open();
+            ch.params_update.take()
+        };
+
+        // The overridden channel gets its own update; the other channel gets
+        // the netdir-derived global one.
+        map.reconfigure_general(None, None, Ok(netdir.clone())).unwrap();
+        assert_eq!(
+            format!("{:?}", take_update(b't').unwrap()),
+            "ChannelsParamsUpdates { padding_enable: None, \
+                padding_parameters: Some(Parameters { \
+                    low_ms: IntegerMilliseconds { value: 42 }, \
+                    high_ms: IntegerMilliseconds { value: 99 } }) }"
+        );
+        assert_eq!(
+            format!("{:?}", take_update(b'u').unwrap()),
+            "ChannelsParamsUpdates { padding_enable: None, \
+                padding_parameters: Some(Parameters { \
+                    low_ms: IntegerMilliseconds { value: 1500 }, \
+                    high_ms: IntegerMilliseconds { value: 9500 } }) }"
+        );
+
+        // Clearing the override restores the global behaviour: a repeated
+        // default netdir now sends no update to the (unaffected) channel.
+        map.set_padding_override(&track_query, None).unwrap();
+        map.reconfigure_general(None, None, Ok(netdir)).unwrap();
+        assert_eq!(take_update(b'u'), None);
+    }
+
+    #[test]
+    fn by_relay_ids() {
+        let map = new_test_channel_map();
+        assert!(map.replace(b'h', ch("hello")).unwrap().is_none());
+
+        // A query carrying the channel's Ed25519 identity finds it.
+        let query = FakeChannel {
+            ident: "hello",
+            usable: true,
+            unused_duration: None,
+            params_update: None,
+            padding_negotiate: None,
+            ed_ident: fake_ed("hello"),
+        };
+        match map.get_by_relay_ids(&query) {
+            Ok(ChannelForTarget::Found(ChannelState::Open(ent))) if ent.channel.ident == "hello" => {}
+            _ => panic!(),
+        }
+
+        // A relay we've never seen is not found, and free to dial.
+        let unknown = FakeChannel {
+            ident: "zonk",
+            usable: true,
+            unused_duration: None,
+            params_update: None,
+            padding_negotiate: None,
+            ed_ident: fake_ed("zonk"),
+        };
+        assert!(matches!(
+            map.get_by_relay_ids(&unknown).unwrap(),
+            ChannelForTarget::Free
+        ));
+
+        // Removing the channel also removes it from the secondary index.
+        map.remove(&b'h').unwrap();
+        assert!(matches!(
+            map.get_by_relay_ids(&query).unwrap(),
+            ChannelForTarget::Free
+        ));
+    }
+
+    #[test]
+    fn dormancy_sends_padding_negotiate() {
+        let map = new_test_channel_map();
+        assert!(map.replace(b't', ch("track")).unwrap().is_none());
+
+        let netdir = tor_netdir::testnet::construct_netdir()
+            .unwrap_if_sufficient()
+            .unwrap();
+        let netdir = Arc::new(netdir);
+
+        let with_ch = |f: &dyn Fn(&mut FakeChannel)| {
+            let mut inner = map.inner.lock().unwrap();
+            let ch = inner.channels.get_mut(&b't').unwrap().unwrap_open();
+            f(ch);
+        };
+
+        // Going dormant tells the peer to stop sending us padding.
+        map.reconfigure_general(None, Some(Dormancy::Dormant), Ok(netdir.clone()))
+            .unwrap();
+        with_ch(&|ch| {
+            assert_eq!(ch.padding_negotiate.take(), Some(PaddingNegotiate::stop()));
+        });
+
+        // Waking back up resumes padding.
+        map.reconfigure_general(None, Some(Dormancy::Active), Ok(netdir))
+            .unwrap();
+        with_ch(&|ch| {
+            assert_eq!(
+                ch.padding_negotiate.take(),
+                Some(PaddingNegotiate::start_default())
+            );
+        });
+    }
+
+    #[test]
+    fn padding_negotiate_only_on_transition() {
+        let map = new_test_channel_map();
+        assert!(map.replace(b't', ch("track")).unwrap().is_none());
+
+        let netdir = tor_netdir::testnet::construct_netdir()
+            .unwrap_if_sufficient()
+            .unwrap();
+        let netdir = Arc::new(netdir);
+
+        let take_negotiate = || {
+            let mut inner = map.inner.lock().unwrap();
+            inner
+                .channels
+                .get_mut(&b't')
+                .unwrap()
+                .unwrap_open()
+                .padding_negotiate
+                .take()
+        };
+
+        // Go dormant once: a STOP is sent.
+        map.reconfigure_general(None, Some(Dormancy::Dormant), Ok(netdir.clone()))
+            .unwrap();
+        assert_eq!(take_negotiate(), Some(PaddingNegotiate::stop()));
+
+        // A further reconfiguration that stays dormant (e.g. a netdir refresh)
+        // must not re-send a STOP cell.
+        map.reconfigure_general(None, Some(Dormancy::Dormant), Ok(netdir.clone()))
+            .unwrap();
+        assert_eq!(take_negotiate(), None);
+
+        // A plain netdir refresh with no dormancy change also sends nothing.
+        map.reconfigure_general(None, None, Ok(netdir)).unwrap();
+        assert_eq!(take_negotiate(), None);
+    }
+
+    #[test]
+    fn padding_reduced_transition_renegotiates() {
+        let map = new_test_channel_map();
+        assert!(map.replace(b't', ch("track")).unwrap().is_none());
+
+        let netdir = tor_netdir::testnet::construct_netdir()
+            .unwrap_if_sufficient()
+            .unwrap();
+        let netdir = Arc::new(netdir);
+
+        let take_negotiate = || {
+            let mut inner = map.inner.lock().unwrap();
+            inner
+                .channels
+                .get_mut(&b't')
+                .unwrap()
+                .unwrap_open()
+                .padding_negotiate
+                .take()
+        };
+
+        // Switching from the default Normal padding to Reduced renegotiates.
+        let reduced = ChannelConfig {
+            padding: PaddingLevel::Reduced,
+            padding_timing_low: None,
+            padding_timing_high: None,
+            max_channels: None,
+        };
+        map.reconfigure_general(Some(&reduced), None, Ok(netdir.clone()))
+            .unwrap();
+        assert_eq!(
+            take_negotiate(),
+            Some(PaddingNegotiate::start_default())
+        );
+
+        // Reconfiguring to the same (Reduced) level sends nothing further.
+        map.reconfigure_general(Some(&reduced), None, Ok(netdir)).unwrap();
+        assert_eq!(take_negotiate(), None);
+    }
+
     #[test]
     fn expire_channels() {
         let map = new_test_channel_map();
@@ -732,11 +1911,196 @@ mod test {
         // Closed channel should be retained
         map.replace(b'h', closed("hello")).unwrap();
 
-        // Return duration until next channel expires
-        assert_eq!(10, map.expire_channels().as_secs());
+        // Return duration until next channel expires.  The deadline is now
+        // tracked as an `Instant`, so allow for the sub-second time that has
+        // elapsed since the channel was inserted.
+        let next = map.expire_channels().as_secs();
+        assert!((9..=10).contains(&next), "unexpected next-expiry: {next}");
         assert!(map.get(&b'w').unwrap().is_some());
         assert!(map.get(&b'y').unwrap().is_some());
         assert!(map.get(&b'h').unwrap().is_some());
         assert!(map.get(&b'g').unwrap().is_none());
     }
+
+    #[test]
+    fn backoff_delay_schedule() {
+        // Zero failures means no delay; thereafter the delay doubles from the
+        // base and saturates at the cap.
+        assert_eq!(backoff_delay(0), Duration::ZERO);
+        assert_eq!(backoff_delay(1), Duration::from_millis(500));
+        assert_eq!(backoff_delay(2), Duration::from_millis(1000));
+        assert_eq!(backoff_delay(3), Duration::from_millis(2000));
+        assert_eq!(backoff_delay(100), BACKOFF_CAP);
+    }
+
+    #[test]
+    fn failure_backoff_blocks_retry() {
+        let map: ChannelMap<FakeChannel> = new_test_channel_map();
+
+        let relay = FakeChannel {
+            ident: "track",
+            usable: true,
+            unused_duration: None,
+            params_update: None,
+            padding_negotiate: None,
+            ed_ident: fake_ed("track"),
+        };
+
+        // A relay we've never failed to reach is retriable right away.
+        assert!(map.retry_blocked_for(&relay).unwrap().is_none());
+
+        // After three consecutive failures the relay is blocked, and the
+        // remaining delay is no longer than the computed backoff for that count.
+        for _ in 0..3 {
+            map.note_build_failure(b't', &relay, FailureKind::Transient).unwrap();
+        }
+        let blocked = map.retry_blocked_for(&relay).unwrap();
+        let blocked = blocked.expect("relay should be in backoff after 3 failures");
+        assert!(blocked > Duration::ZERO);
+        assert!(blocked <= backoff_delay(3));
+
+        // Once its computed delay has elapsed, the relay becomes retriable again
+        // without its failure count being reset.
+        map.rewind_retry(&relay, backoff_delay(3));
+        assert!(map.retry_blocked_for(&relay).unwrap().is_none());
+
+        // A further failure still extends the backoff (count carried over).
+        map.note_build_failure(b't', &relay, FailureKind::Transient).unwrap();
+        assert!(map.retry_blocked_for(&relay).unwrap().is_some());
+
+        // A successful build clears the backoff entirely.
+        map.note_build_success(&relay).unwrap();
+        assert!(map.retry_blocked_for(&relay).unwrap().is_none());
+    }
+
+    #[test]
+    fn backoff_folds_into_expiry_wakeup() {
+        let map: ChannelMap<FakeChannel> = new_test_channel_map();
+
+        let relay = FakeChannel {
+            ident: "track",
+            usable: true,
+            unused_duration: None,
+            params_update: None,
+            padding_negotiate: None,
+            ed_ident: fake_ed("track"),
+        };
+
+        // With an empty map the wakeup falls back to the historical floor.
+        assert_eq!(map.expire_channels(), Duration::from_secs(180));
+
+        // A relay in backoff pulls the next wakeup in to (at most) its retry delay.
+        map.note_build_failure(b't', &relay, FailureKind::Transient).unwrap();
+        assert!(map.expire_channels() <= backoff_delay(1));
+    }
+
+    #[test]
+    fn transient_retriable_permanent_quarantined() {
+        let map: ChannelMap<FakeChannel> = new_test_channel_map();
+
+        let relay = FakeChannel {
+            ident: "track",
+            usable: true,
+            unused_duration: None,
+            params_update: None,
+            padding_negotiate: None,
+            ed_ident: fake_ed("track"),
+        };
+
+        // A transient timeout only feeds the backoff: the relay stays a normal
+        // retry candidate and leaves no tombstone in the map, but
+        // get_by_relay_ids still reports it as not-yet-retriable.
+        map.note_build_failure(b't', &relay, FailureKind::Transient)
+            .unwrap();
+        assert!(map.get(&b't').unwrap().is_none());
+        assert!(matches!(
+            map.get_by_relay_ids(&relay).unwrap(),
+            ChannelForTarget::Blocked(_)
+        ));
+        assert!(map.retry_blocked_for(&relay).unwrap().is_some());
+
+        // A key mismatch is permanent: it quarantines the relay so `get` and
+        // `get_by_relay_ids` report it as unusable rather than redialing it.
+        map.note_build_failure(b't', &relay, FailureKind::Permanent)
+            .unwrap();
+        assert!(matches!(
+            map.get(&b't').unwrap(),
+            Some(ChannelState::Quarantined(_))
+        ));
+        assert!(matches!(
+            map.get_by_relay_ids(&relay).unwrap(),
+            ChannelForTarget::Found(ChannelState::Quarantined(_))
+        ));
+
+        // The quarantine window is much longer than the backoff cap, so a reap
+        // pass does not clear it: the relay stays quarantined.
+        assert!(QUARANTINE_WINDOW > BACKOFF_CAP);
+        let _ = map.expire_channels();
+        assert!(matches!(
+            map.get(&b't').unwrap(),
+            Some(ChannelState::Quarantined(_))
+        ));
+
+        // A subsequent success lifts the quarantine entirely.
+        map.note_build_success(&relay).unwrap();
+        assert!(map.get(&b't').unwrap().is_none());
+        assert!(matches!(
+            map.get_by_relay_ids(&relay).unwrap(),
+            ChannelForTarget::Free
+        ));
+    }
+
+    #[test]
+    fn capacity_evicts_lru_idle() {
+        let map = new_test_channel_map();
+        map.set_capacity(Some(2));
+
+        // Two idle channels, least-recently-used last.
+        map.replace(
+            b'a',
+            ch_with_details("aaaaa", Duration::from_secs(3600), Some(10)),
+        )
+        .unwrap();
+        map.replace(
+            b'b',
+            ch_with_details("bbbbb", Duration::from_secs(3600), Some(100)),
+        )
+        .unwrap();
+
+        // Inserting a third channel evicts the most-idle one (`b`).
+        map.replace(
+            b'c',
+            ch_with_details("ccccc", Duration::from_secs(3600), Some(5)),
+        )
+        .unwrap();
+
+        assert!(map.get(&b'a').unwrap().is_some());
+        assert!(map.get(&b'b').unwrap().is_none());
+        assert!(map.get(&b'c').unwrap().is_some());
+    }
+
+    #[test]
+    fn capacity_never_evicts_in_use_or_closed() {
+        let map = new_test_channel_map();
+        map.set_capacity(Some(1));
+
+        // An in-use channel (`duration_unused` is `None`) and a closed channel
+        // are both ineligible for eviction, so the map stays over capacity
+        // rather than dropping either of them.
+        map.replace(
+            b'u',
+            ch_with_details("inuse", Duration::from_secs(3600), None),
+        )
+        .unwrap();
+        map.replace(b'h', closed("hello")).unwrap();
+
+        assert!(map.get(&b'u').unwrap().is_some());
+        assert!(map.get(&b'h').unwrap().is_some());
+
+        // Because we are still over capacity with nothing evictable, the next
+        // poll is requested no later than the capacity recheck interval.
+        assert!(map.expire_channels() <= CAPACITY_RECHECK_INTERVAL);
+        assert!(map.get(&b'u').unwrap().is_some());
+        assert!(map.get(&b'h').unwrap().is_some());
+    }
 }
@@ -30,4 +30,222 @@ impl Authority {
     pub fn matches_keyid(&self, id: &AuthCertKeyIds) -> bool {
         self.v3ident == id.id_fingerprint
     }
-}
\ No newline at end of file
+}
+
+/// Policy for how many distinct authorities must sign a consensus before a
+/// client accepts it.
+#[derive(Debug, Clone)]
+pub enum SignaturePolicy {
+    /// More than half of the listed authorities must sign (the Tor rule).
+    Majority,
+    /// An explicit minimum number of distinct authorities must sign.
+    AtLeast(usize),
+}
+
+impl SignaturePolicy {
+    /// Return the number of distinct authority signatures required given a set
+    /// of `total` listed authorities.
+    fn required(&self, total: usize) -> usize {
+        match self {
+            SignaturePolicy::Majority => total / 2 + 1,
+            SignaturePolicy::AtLeast(n) => *n,
+        }
+    }
+}
+
+/// The outcome of checking a consensus's signatures against an [`AuthoritySet`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum SignatureCheck {
+    /// Enough distinct recognized authorities signed the consensus.
+    Valid,
+    /// A signature was presented from a key ID that no configured authority
+    /// recognizes.
+    UnknownSigner,
+    /// Every signer was recognized, but not enough distinct authorities signed.
+    NotEnoughSignatures {
+        /// Number of distinct recognized authorities that signed.
+        found: usize,
+        /// Number of signatures required by the policy.
+        required: usize,
+    },
+}
+
+/// The set of directory authorities a client trusts, together with the policy
+/// for how many of them must sign a consensus.
+///
+/// Where [`Authority`] answers "does this one authority match this key ID",
+/// `AuthoritySet` answers the whole consensus-acceptance question: it maps each
+/// presented signature to at most one authority and decides whether enough
+/// distinct authorities signed.
+#[derive(Debug, Clone)]
+pub struct AuthoritySet {
+    /// The configured authorities.
+    authorities: Vec<Authority>,
+    /// How many distinct signatures we require.
+    policy: SignaturePolicy,
+}
+
+impl AuthoritySet {
+    /// Construct a new set of authorities that requires a majority of them to
+    /// sign a consensus.
+    pub fn new(authorities: Vec<Authority>) -> Self {
+        AuthoritySet {
+            authorities,
+            policy: SignaturePolicy::Majority,
+        }
+    }
+
+    /// Construct a new set of authorities with an explicit signature policy.
+    pub fn with_policy(authorities: Vec<Authority>, policy: SignaturePolicy) -> Self {
+        AuthoritySet {
+            authorities,
+            policy,
+        }
+    }
+
+    /// The number of distinct authority signatures a consensus needs to be
+    /// accepted.
+    pub fn required_signatures(&self) -> usize {
+        self.policy.required(self.authorities.len())
+    }
+
+    /// Decide whether the signatures on a consensus — presented as the key IDs
+    /// that signed it — are enough for the consensus to be accepted.
+    ///
+    /// Each key ID is mapped to at most one authority in the set; a repeated
+    /// signature from the same authority is only counted once, and a signature
+    /// from an unrecognized signer rejects the consensus outright.  The result
+    /// distinguishes "not enough signatures", "unknown signer", and "valid".
+    pub fn check_signatures<'a>(
+        &self,
+        signers: impl IntoIterator<Item = &'a AuthCertKeyIds>,
+    ) -> SignatureCheck {
+        let required = self.required_signatures();
+        // Track which authorities have been counted (by index) so a duplicate
+        // signature from the same authority is not double-counted.
+        let mut satisfied = vec![false; self.authorities.len()];
+        for keyid in signers {
+            match self
+                .authorities
+                .iter()
+                .position(|a| a.matches_keyid(keyid))
+            {
+                Some(idx) => satisfied[idx] = true,
+                None => return SignatureCheck::UnknownSigner,
+            }
+        }
+        let found = satisfied.iter().filter(|counted| **counted).count();
+        if found >= required {
+            SignatureCheck::Valid
+        } else {
+            SignatureCheck::NotEnoughSignatures { found, required }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::unwrap_used)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+
+    use super::*;
+
+    /// Build a throwaway RSA identity fingerprint from a repeated byte, distinct
+    /// enough for these tests to tell authorities and signers apart.
+    fn fake_id(byte: u8) -> RSAIdentity {
+        RSAIdentity::from_bytes(&[byte; 20]).expect("20 bytes is a valid RSAIdentity")
+    }
+
+    /// Build a throwaway authority whose identity is `fake_id(byte)`.
+    fn fake_authority(byte: u8) -> Authority {
+        Authority::new(format!("auth{byte}"), fake_id(byte))
+    }
+
+    /// Build a key-ID pair as if presented by the authority for `byte`.
+    fn fake_keyid(byte: u8) -> AuthCertKeyIds {
+        AuthCertKeyIds {
+            id_fingerprint: fake_id(byte),
+            sk_fingerprint: fake_id(byte),
+        }
+    }
+
+    #[test]
+    fn valid_with_enough_signatures() {
+        let set = AuthoritySet::new(vec![
+            fake_authority(1),
+            fake_authority(2),
+            fake_authority(3),
+        ]);
+        assert_eq!(set.required_signatures(), 2);
+        let signers = vec![fake_keyid(1), fake_keyid(2)];
+        assert_eq!(set.check_signatures(&signers), SignatureCheck::Valid);
+    }
+
+    #[test]
+    fn not_enough_signatures() {
+        let set = AuthoritySet::new(vec![
+            fake_authority(1),
+            fake_authority(2),
+            fake_authority(3),
+        ]);
+        let signers = vec![fake_keyid(1)];
+        assert_eq!(
+            set.check_signatures(&signers),
+            SignatureCheck::NotEnoughSignatures {
+                found: 1,
+                required: 2
+            }
+        );
+    }
+
+    #[test]
+    fn unknown_signer_rejects_outright() {
+        let set = AuthoritySet::new(vec![
+            fake_authority(1),
+            fake_authority(2),
+            fake_authority(3),
+        ]);
+        // One recognized signer and one from an authority not in the set.
+        let signers = vec![fake_keyid(1), fake_keyid(99)];
+        assert_eq!(
+            set.check_signatures(&signers),
+            SignatureCheck::UnknownSigner
+        );
+    }
+
+    #[test]
+    fn duplicate_signer_counted_once() {
+        let set = AuthoritySet::new(vec![
+            fake_authority(1),
+            fake_authority(2),
+            fake_authority(3),
+        ]);
+        // The same authority signing three times still counts as one.
+        let signers = vec![fake_keyid(1), fake_keyid(1), fake_keyid(1)];
+        assert_eq!(
+            set.check_signatures(&signers),
+            SignatureCheck::NotEnoughSignatures {
+                found: 1,
+                required: 2
+            }
+        );
+    }
+
+    #[test]
+    fn majority_boundary_odd_vs_even() {
+        // Odd: 5 authorities need 3 (strictly more than half).
+        let odd = AuthoritySet::new((1_u8..=5).map(fake_authority).collect());
+        assert_eq!(odd.required_signatures(), 3);
+
+        // Even: 4 authorities also need 3, not 2 -- an exact half is not a
+        // majority.
+        let even = AuthoritySet::new((1_u8..=4).map(fake_authority).collect());
+        assert_eq!(even.required_signatures(), 3);
+    }
+}
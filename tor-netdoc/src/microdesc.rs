@@ -110,14 +110,41 @@ impl MicrodescAnnotation {
 impl Microdesc {
     /// Parse a string into a new microdescriptor.
     pub fn parse(s: &str) -> Result<Microdesc> {
-        let mut items = crate::tokenize::NetDocReader::new(s);
-        Self::parse_from_reader(&mut items)
+        let mut reader = MicrodescReader::new(s);
+        reader
+            .next()
+            .unwrap_or(Err(Error::MissingToken("onion-key")))
+    }
+}
+
+/// An iterator that parses a batch of microdescriptors from a single
+/// concatenated document, as returned by a directory cache.
+///
+/// Each item is a [`Microdesc`] whose `sha256` covers exactly the bytes of
+/// that one record (from its `onion-key` up to the next record's
+/// `onion-key`), so that each descriptor can be correlated against the `m`
+/// lines in a consensus.
+pub struct MicrodescReader<'a> {
+    /// The underlying document reader.
+    reader: NetDocReader<'a, MicrodescKW>,
+    /// The whole document, for computing per-record digests.
+    s: &'a str,
+    /// Byte offset at which the next record begins.
+    pos: usize,
+}
+
+impl<'a> MicrodescReader<'a> {
+    /// Construct a `MicrodescReader` to read all the microdescriptors in `s`.
+    pub fn new(s: &'a str) -> Self {
+        let reader = NetDocReader::new(s);
+        MicrodescReader { reader, s, pos: 0 }
     }
 
-    /// Extract a single microdescriptor from a NetDocReader.
-    fn parse_from_reader(reader: &mut NetDocReader<'_, MicrodescKW>) -> Result<Microdesc> {
+    /// Parse the next microdescriptor from the document.
+    fn parse_one(&mut self) -> Result<Microdesc> {
         use MicrodescKW::*;
-        let s = reader.str();
+        let s = self.s;
+        let reader = &mut self.reader;
 
         let mut first_onion_key = true;
         // We'll pause at the next annotation, or at the _second_ onion key.
@@ -145,7 +172,7 @@ impl Microdesc {
             if kwd != "onion-key" {
                 return Err(Error::MissingToken("onion-key"));
             }
-            util::str_offset(s, kwd).unwrap()
+            next_onion_key(s, self.pos).unwrap_or_else(|| util::str_offset(s, kwd).unwrap())
         };
 
         let body = MICRODESC_RULES.parse(&mut items)?;
@@ -191,9 +218,13 @@ impl Microdesc {
             }
         };
 
-        // TODO: This is the whole string! It just isn't the
-        // microdescriptor.
-        let sha256 = d::Sha256::digest(&s[start_pos..].as_bytes()).into();
+        // The digest covers exactly this record: from its own `onion-key`
+        // up to (but not including) the next record's `onion-key`, or the end
+        // of the document if this is the last record.
+        let end_pos =
+            next_onion_key(s, start_pos + "onion-key".len()).unwrap_or_else(|| s.len());
+        self.pos = end_pos;
+        let sha256 = d::Sha256::digest(s[start_pos..end_pos].as_bytes()).into();
 
         Ok(Microdesc {
             sha256,
@@ -206,6 +237,34 @@ impl Microdesc {
         })
     }
 }
+
+impl<'a> Iterator for MicrodescReader<'a> {
+    type Item = Result<Microdesc>;
+    fn next(&mut self) -> Option<Result<Microdesc>> {
+        // If there is no remaining `onion-key` line, the document is
+        // exhausted.
+        next_onion_key(self.s, self.pos)?;
+        Some(self.parse_one())
+    }
+}
+
+/// Find the byte offset of the next record's `onion-key` line at or after
+/// `from` in `s`.
+///
+/// Matches only an `onion-key` keyword at the start of a line (i.e. preceded
+/// by a newline or the start of the document), so that the `ntor-onion-key`
+/// line is not mistaken for a record boundary.
+fn next_onion_key(s: &str, from: usize) -> Option<usize> {
+    let mut search = from;
+    while let Some(rel) = s[search..].find("onion-key\n") {
+        let at = search + rel;
+        if at == 0 || s.as_bytes()[at - 1] == b'\n' {
+            return Some(at);
+        }
+        search = at + "onion-key".len();
+    }
+    None
+}
 #[cfg(test)]
 mod test {
     use super::*;
@@ -216,4 +275,21 @@ mod test {
         let _md = Microdesc::parse(TESTDATA)?;
         Ok(())
     }
+
+    #[test]
+    fn parse_multiple() -> Result<()> {
+        // A concatenation of several copies of the same microdesc is a valid
+        // (if redundant) directory document; the reader should yield one
+        // record per `onion-key`, and each record's digest should cover only
+        // its own bytes, so all three digests match the single-parse result.
+        let single = Microdesc::parse(TESTDATA)?;
+        let doc = format!("{0}{0}{0}", TESTDATA);
+        let mds: Result<Vec<_>> = MicrodescReader::new(&doc).collect();
+        let mds = mds?;
+        assert_eq!(mds.len(), 3);
+        for md in &mds {
+            assert_eq!(md.sha256, single.sha256);
+        }
+        Ok(())
+    }
 }
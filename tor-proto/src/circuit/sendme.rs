@@ -12,22 +12,70 @@
 
 use futures::channel::oneshot;
 use futures::lock::Mutex;
+use tor_error::internal;
 
 use std::collections::VecDeque;
 use std::sync::Arc;
 
-// XXXX Three problems with this tag:
+use crate::Result;
+
+// XXXX Two problems with this tag:
 // XXXX - First, we need to support unauthenticated flow control.
 // XXXX - Second, this tag type could be different for each layer, if we
 // XXXX   eventually have an authenticator that isn't 20 bytes long.
-// XXXX - Third, we want the comparison to happen with a constant-time
-// XXXX   operation.
 
 /// Tag type used in regular v1 sendme cells.
-pub type CircTag = [u8; 20];
+///
+/// This wraps the 20-byte authenticator carried by an authenticated
+/// circuit-level SENDME.  Because the tag is secret-dependent, its equality
+/// check is constant-time (see the [`PartialEq`] impl below) so that an
+/// attacker cannot learn, from comparison timing, how many leading bytes of a
+/// guessed tag were correct.
+#[derive(Clone, Debug)]
+pub struct CircTag([u8; 20]);
+
+impl From<[u8; 20]> for CircTag {
+    fn from(bytes: [u8; 20]) -> Self {
+        CircTag(bytes)
+    }
+}
+
+impl PartialEq for CircTag {
+    fn eq(&self, other: &Self) -> bool {
+        // Fold an XOR accumulator over every byte rather than returning early on
+        // the first mismatch, so the comparison takes the same time regardless
+        // of where (or whether) the tags differ.
+        let mut acc = 0_u8;
+        for (a, b) in self.0.iter().zip(other.0.iter()) {
+            acc |= a ^ b;
+        }
+        acc == 0
+    }
+}
+impl Eq for CircTag {}
+
 /// Absence of a tag, as with stream cells.
 pub type NoTag = ();
 
+/// Whether a send window authenticates the SENDME tags it receives.
+///
+/// This is fixed for the lifetime of a window at [`SendWindow::new`] time, based
+/// on whether the peer negotiated `FlowCtrl=1`, rather than being decided per
+/// cell.  Both modes keep identical window arithmetic (see [`SendWindow::put`]);
+/// the only difference is that [`FlowCtrlMode::Unauthenticated`] does not treat
+/// a tag mismatch as a protocol violation.
+///
+/// Once every relay advertises `FlowCtrl=1` this distinction — and the whole
+/// enum — can be removed without touching the window arithmetic.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FlowCtrlMode {
+    /// The peer supports authenticated flow control; SENDME tags must match.
+    Authenticated,
+    /// The peer does not support authenticated flow control; tags are still
+    /// tracked but a mismatch is tolerated (and reported).
+    Unauthenticated,
+}
+
 /// A circuit's send window.
 pub type CircSendWindow = SendWindow<CircParams, CircTag>;
 /// A stream's send window.
@@ -68,31 +116,76 @@ where
     /// If present, a oneshot that we are blocking on before we can send
     /// any more data.
     unblock: Option<oneshot::Sender<()>>,
+    /// Whether incoming SENDME tags are authenticated for this window.
+    mode: FlowCtrlMode,
+    /// The maximum and increment governing this window.
+    params: WindowParameters,
 }
 
-/// Helper: parameterizes a window to determine its maximum and its increment.
-pub trait WindowParams {
+/// The maximum size and per-SENDME increment of a flow-control window.
+///
+/// These used to be fixed per window *type*; they are now carried in the window
+/// state so the directory/consensus layer can feed live flow-control parameters
+/// (e.g. `circwindow`) into new circuits, and so congestion-control schemes can
+/// vary the increment without changing the generic plumbing.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct WindowParameters {
+    /// Largest allowable value for this window.
+    maximum: u16,
+    /// Increment applied for each incoming SENDME.
+    increment: u16,
+}
+
+impl WindowParameters {
+    /// Construct a set of window parameters.
+    ///
+    /// Returns an error if `increment` is zero. The window arithmetic divides
+    /// and takes remainders by the increment (see [`SendWindow::new_with_params`]
+    /// and [`SendWindow::take`]), so a zero increment — which a malformed
+    /// consensus could otherwise feed in via `new_with_params` — would panic
+    /// rather than report the bad parameter.
+    pub fn new(maximum: u16, increment: u16) -> Result<Self> {
+        if increment == 0 {
+            return Err(internal!("window increment must be nonzero").into());
+        }
+        Ok(WindowParameters { maximum, increment })
+    }
     /// Largest allowable value for this window.
-    fn get_maximum() -> u16;
-    /// Increment for this window.
-    fn get_increment() -> u16;
+    pub fn maximum(&self) -> u16 {
+        self.maximum
+    }
+    /// Increment applied for each incoming SENDME.
+    pub fn increment(&self) -> u16 {
+        self.increment
+    }
+}
+
+/// Helper: supplies the default [`WindowParameters`] for a kind of window.
+///
+/// The parameters are no longer resolved from this type at runtime — they are
+/// stored in the window — but the marker types still carry the compiled-in
+/// defaults used when no override is supplied.
+pub trait WindowParams {
+    /// The default window parameters for this kind of window.
+    ///
+    /// # Panics
+    ///
+    /// Panics if an implementation supplies an invalid `(maximum, increment)`
+    /// pair (for example, a zero increment) to [`WindowParameters::new`]. The
+    /// defaults below are fixed, known-valid constants, so this cannot happen
+    /// for them.
+    fn default_parameters() -> WindowParameters;
 }
 pub struct CircParams;
 impl WindowParams for CircParams {
-    fn get_maximum() -> u16 {
-        1000
-    }
-    fn get_increment() -> u16 {
-        100
+    fn default_parameters() -> WindowParameters {
+        WindowParameters::new(1000, 100).expect("default circuit window parameters are invalid")
     }
 }
 pub struct StreamParams;
 impl WindowParams for StreamParams {
-    fn get_maximum() -> u16 {
-        500
-    }
-    fn get_increment() -> u16 {
-        50
+    fn default_parameters() -> WindowParameters {
+        WindowParameters::new(500, 50).expect("default stream window parameters are invalid")
     }
 }
 
@@ -101,14 +194,32 @@ where
     P: WindowParams,
     T: PartialEq + Eq + Clone,
 {
-    /// Construct a new SendWindow.
-    pub fn new(window: u16) -> SendWindow<P, T> {
-        let increment = P::get_increment();
+    /// Construct a new SendWindow using the default parameters for `P`.
+    ///
+    /// `mode` selects whether incoming SENDME tags are authenticated, and is
+    /// fixed for the life of the window based on the peer's negotiated protocol
+    /// support.
+    pub fn new(window: u16, mode: FlowCtrlMode) -> SendWindow<P, T> {
+        Self::new_with_params(window, P::default_parameters(), mode)
+    }
+
+    /// Construct a new SendWindow with explicit [`WindowParameters`].
+    ///
+    /// This lets the directory/consensus layer supply live flow-control
+    /// parameters rather than using the compiled-in defaults for `P`.
+    pub fn new_with_params(
+        window: u16,
+        params: WindowParameters,
+        mode: FlowCtrlMode,
+    ) -> SendWindow<P, T> {
+        let increment = params.increment();
         let capacity = (window + increment - 1) / increment;
         let inner = SendWindowInner {
             window,
             tags: VecDeque::with_capacity(capacity as usize),
             unblock: None,
+            mode,
+            params,
         };
         SendWindow {
             w: Arc::new(Mutex::new(inner)),
@@ -130,13 +241,17 @@ where
     /// originated the cell.  It will get cloned and recorded if we'll
     /// need to check for it later.
     ///
-    /// Return the number of cells left in the window
-    pub async fn take(&mut self, tag: &T) -> u16 {
+    /// Return the number of cells left in the window.
+    ///
+    /// Returns an error (rather than panicking) if the window is already blocked
+    /// on another sender, or if that sender is dropped while we wait: either
+    /// means the circuit is in an inconsistent state and should be torn down.
+    pub async fn take(&mut self, tag: &T) -> Result<u16> {
         loop {
             let wait_on = {
                 let mut w = self.w.lock().await;
                 let oldval = w.window;
-                if oldval % P::get_increment() == 0 && oldval != P::get_maximum() {
+                if oldval % w.params.increment() == 0 && oldval != w.params.maximum() {
                     // We record this tag.
                     // TODO: I'm not saying that this cell in particular
                     // matches the spec, but Tor seems to like it.
@@ -144,30 +259,33 @@ where
                 }
                 if let Some(val) = w.window.checked_sub(1) {
                     w.window = val;
-                    return val;
+                    return Ok(val);
                 }
 
                 // Window is zero; can't send yet.
                 let (send, recv) = oneshot::channel::<()>();
 
-                let old = w.unblock.replace(send);
-                assert!(old.is_none()); // XXXX can this happen?
+                if w.unblock.replace(send).is_some() {
+                    return Err(internal!(
+                        "Two SendWindow::take() calls blocked on the same window"
+                    )
+                    .into());
+                }
                 recv
             };
             // Wait on this receiver while _not_ holding the lock.
-
-            // XXXX Danger: can this unwrap fail? I think it can't, since
-            // the sender can't be cancelled as long as there's a refcount
-            // to it.
-            wait_on.await.unwrap()
+            if wait_on.await.is_err() {
+                return Err(internal!("SendWindow unblock sender dropped while waiting").into());
+            }
         }
     }
 
     /// Handle an incoming sendme with a provided tag.
     ///
-    /// If the tag is None, then we don't enforce tag requirements. (We can
-    /// remove this option once we no longer support getting SENDME cells
-    /// from relays without the FlowCtrl=1 protocol.)
+    /// In [`FlowCtrlMode::Authenticated`] the tag must match the one we recorded
+    /// when the corresponding cell was sent; in [`FlowCtrlMode::Unauthenticated`]
+    /// the recorded tag is still popped (so the window arithmetic is identical)
+    /// but a mismatch is tolerated and merely logged.
     ///
     /// On success, return the number of cells left in the window.
     ///
@@ -176,15 +294,25 @@ where
     pub async fn put(&mut self, tag: Option<T>) -> Option<u16> {
         let mut w = self.w.lock().await;
 
-        match (w.tags.pop_front(), tag) {
-            (Some(t), Some(tag)) if t == tag => {} // this is the right tag.
-            (Some(_), None) => {}                  // didn't need a tag.
+        // Always pop the bookkeeping entry first, so both modes advance the
+        // window identically regardless of the tag check's outcome.
+        let expected = w.tags.pop_front();
+        match (w.mode, expected, tag) {
+            (_, Some(t), Some(tag)) if t == tag => {} // this is the right tag.
+            (_, Some(_), None) => {}                   // didn't need a tag.
+            (FlowCtrlMode::Unauthenticated, Some(_), Some(_)) => {
+                // A mismatch we would have rejected under authenticated flow
+                // control; surface it for logs/metrics but keep going.
+                tracing::debug!(
+                    "unauthenticated SENDME tag mismatch: would have failed the tag check"
+                );
+            }
             _ => {
                 return None;
             } // Bad tag or unexpected sendme.
         }
 
-        let v = w.window.checked_add(P::get_increment())?;
+        let v = w.window.checked_add(w.params.increment())?;
         w.window = v;
 
         if let Some(send) = w.unblock.take() {
@@ -200,14 +328,22 @@ where
 /// Structure to track when we need to send SENDME cells for incoming data.
 pub struct RecvWindow<P: WindowParams> {
     window: u16,
+    /// The maximum and increment governing this window.
+    params: WindowParameters,
     _dummy: std::marker::PhantomData<P>,
 }
 
 impl<P: WindowParams> RecvWindow<P> {
-    /// Create a new RecvWindow.
+    /// Create a new RecvWindow using the default parameters for `P`.
     pub fn new(window: u16) -> RecvWindow<P> {
+        Self::new_with_params(window, P::default_parameters())
+    }
+
+    /// Create a new RecvWindow with explicit [`WindowParameters`].
+    pub fn new_with_params(window: u16, params: WindowParameters) -> RecvWindow<P> {
         RecvWindow {
             window,
+            params,
             _dummy: std::marker::PhantomData,
         }
     }
@@ -224,14 +360,25 @@ impl<P: WindowParams> RecvWindow<P> {
             self.window = x;
             // TODO: same note as in SendWindow.take(). I don't know if
             // this truly matches the spec, but Tot tor accepts it.
-            Some(oldval % P::get_increment() == 0 && oldval != P::get_maximum())
+            Some(oldval % self.params.increment() == 0 && oldval != self.params.maximum())
         } else {
             None
         }
     }
 
     /// Called when we've just send a SENDME.
-    pub fn put(&mut self) {
-        self.window = self.window.checked_add(P::get_increment()).unwrap();
+    ///
+    /// Returns an error (rather than panicking) if growing the window by the
+    /// increment would exceed the window's
+    /// [`maximum`](WindowParameters::maximum), which a misbehaving peer could
+    /// otherwise use to over-grow the window.
+    pub fn put(&mut self) -> Result<()> {
+        let v = self
+            .window
+            .checked_add(self.params.increment())
+            .filter(|v| *v <= self.params.maximum())
+            .ok_or_else(|| internal!("RecvWindow grew beyond its maximum"))?;
+        self.window = v;
+        Ok(())
     }
 }